@@ -0,0 +1,13 @@
+@echo off
+
+route delete 1.0.1.0
+route delete 1.0.2.0
+route delete 10.0.0.0
+route delete 100.64.0.0
+route delete 169.254.0.0
+route delete 172.16.0.0
+route delete 192.168.0.0
+route delete 224.0.0.0
+route delete fc00::
+route delete fe80::
+route delete ff00::
\ No newline at end of file
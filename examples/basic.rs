@@ -4,10 +4,11 @@ use chnroutes::{Result, Source, Target};
 #[tokio::main]
 async fn main() -> Result<()> {
     /// Get the CN IPs from APNIC
-    let cn_ip_results: Vec<ipnet::IpNet> = chnroutes::source::apnic::fetch_ip_data()?;
+    let cn_ip_results: Vec<ipnet::IpNet> = chnroutes::source::apnic::fetch_ip_data(&["CN"])?;
     /// Get the user script
-    let user_script: Result<(String, Option<String>)> = Target::Linux.export_str(&Source::apnic);
+    let user_script: Result<(String, Option<String>)> =
+        Target::Linux.export_str(&Source::apnic, &Default::default());
     /// Apply rules to system route table
-    chnroutes::up(&Default::default()).await?;
+    chnroutes::up(&Source::apnic, &Default::default()).await?;
     Ok(())
 }
@@ -1,7 +1,159 @@
-use std::{env, env::temp_dir, io, path::PathBuf, time::Duration};
+use std::{
+    env,
+    env::temp_dir,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
 
 use crate::error::CacheError;
 
+/// Environment variable overriding the cache directory.
+pub const CACHE_DIR_ENV: &str = "CHNROUTES_CACHE_DIR";
+
+/// Cache directory set by [`set_cache_dir`].
+static CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Set the directory of cache files, taking precedence over [`CACHE_DIR_ENV`]
+/// and the platform default.
+pub fn set_cache_dir(dir: impl Into<PathBuf>) {
+    *CACHE_DIR.write().unwrap() = Some(dir.into());
+}
+
+/// Point the cache directory at a temporary one, so that tests never touch the
+/// real cache.
+#[cfg(test)]
+pub(crate) fn use_test_cache_dir() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| set_cache_dir(temp_dir().join(concat!(env!("CARGO_PKG_NAME"), "-test"))));
+}
+
+/// Get the directory of cache files: the one set by [`set_cache_dir`], or by
+/// [`CACHE_DIR_ENV`], or `/var/cache/chnroutes` when run as root on unix, or
+/// the platform cache directory, like `$XDG_CACHE_HOME/chnroutes`.
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = CACHE_DIR.read().unwrap().as_ref() {
+        return dir.clone();
+    }
+    if let Some(dir) = env::var_os(CACHE_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return dir.into();
+    }
+    #[cfg(unix)]
+    // SAFETY: geteuid has no preconditions and never fails.
+    if unsafe { libc::geteuid() } == 0 {
+        return PathBuf::from("/var/cache").join(env!("CARGO_PKG_NAME"));
+    }
+    dirs::cache_dir()
+        .unwrap_or_else(temp_dir)
+        .join(env!("CARGO_PKG_NAME"))
+}
+
+/// Write `bytes` to a temporary file next to `path` and rename it to `path`,
+/// so that readers see either the old or the new file, never a torn one.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = (|| {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Create the cache directory if it does not exist, only accessible by the
+/// current user so that other users cannot plant data in it. An existing one is
+/// checked by [`check_dir`] instead.
+fn create_dir(dir: &Path) -> io::Result<()> {
+    if dir.is_dir() {
+        return check_dir(dir);
+    }
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Check that an existing cache directory is owned by the current user and not
+/// writable by others, so that nobody else can have planted data in it.
+#[cfg(unix)]
+fn check_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let metadata = std::fs::metadata(dir)?;
+    // SAFETY: geteuid has no preconditions and never fails.
+    let problem = if metadata.uid() != unsafe { libc::geteuid() } {
+        "is owned by another user"
+    } else if metadata.mode() & 0o022 != 0 {
+        "is writable by other users"
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "cache directory {} {problem}, refusing to use it",
+            dir.display()
+        ),
+    ))
+}
+
+#[cfg(not(unix))]
+fn check_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether `path` still refers to the open `file`.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let file_metadata = file.metadata()?;
+    Ok(metadata.dev() == file_metadata.dev() && metadata.ino() == file_metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> io::Result<bool> {
+    Ok(path.exists())
+}
+
+/// HTTP validators of a cached response, sent back to only download the data
+/// again when it has changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
 /// A file Cache with an expire time, to store IP source file for a few days.
 pub struct Cache {
     name: String,
@@ -18,19 +170,86 @@ impl Cache {
 
     /// Get the path of the cache file
     fn get_path(&self) -> PathBuf {
-        temp_dir().join(env!("CARGO_PKG_NAME")).join(&self.name)
+        cache_dir().join(&self.name)
     }
 
-    /// Save file to cache
+    /// Get the path of the file keeping the [`Validators`] of the cache file
+    fn get_meta_path(&self) -> PathBuf {
+        cache_dir().join(format!("{}.meta", self.name))
+    }
+
+    /// Get the path of the file locked by [`Cache::lock`]
+    fn get_lock_path(&self) -> PathBuf {
+        cache_dir().join(format!("{}.lock", self.name))
+    }
+
+    /// Save file to cache, dropping the validators of the previous file
     pub fn save<'a>(&self, bytes: &'a [u8]) -> io::Result<&'a [u8]> {
+        self.save_with_validators(bytes, &Validators::default())
+    }
+
+    /// Save file to cache along with the validators of the response it came
+    /// from. Saving the same file again refreshes it.
+    pub fn save_with_validators<'a>(
+        &self,
+        bytes: &'a [u8],
+        validators: &Validators,
+    ) -> io::Result<&'a [u8]> {
+        let mut meta = String::new();
+        if let Some(etag) = &validators.etag {
+            meta.push_str(&format!("etag: {etag}\n"));
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            meta.push_str(&format!("last-modified: {last_modified}\n"));
+        }
+        self.save_with_meta(bytes, &meta)
+    }
+
+    /// Save built-in data to cache, marked so that it is not taken for the
+    /// previous download, see [`Cache::is_builtin`]
+    pub fn save_builtin<'a>(&self, bytes: &'a [u8]) -> io::Result<&'a [u8]> {
+        self.save_with_meta(bytes, "builtin: true\n")
+    }
+
+    /// Save file to cache along with the lines of its meta file, if there are
+    /// some
+    fn save_with_meta<'a>(&self, bytes: &'a [u8], meta: &str) -> io::Result<&'a [u8]> {
         let path = self.get_path();
-        std::fs::create_dir_all(path.parent().unwrap())?;
-        std::fs::write(path, bytes)?;
+        create_dir(path.parent().unwrap())?;
+        // Drop the old meta file first, it must never go with other data.
+        let meta_path = self.get_meta_path();
+        if meta_path.exists() {
+            std::fs::remove_file(&meta_path)?;
+        }
+        write_atomic(&path, bytes)?;
+        if !meta.is_empty() {
+            write_atomic(&meta_path, meta.as_bytes())?;
+        }
         Ok(bytes)
     }
 
+    /// Take an advisory lock on the cache file, waiting for other processes
+    /// holding it. The lock is released when the returned file is dropped
+    pub fn lock(&self) -> io::Result<File> {
+        let path = self.get_lock_path();
+        create_dir(path.parent().unwrap())?;
+        loop {
+            let file = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            file.lock()?;
+            // Retry if the lock file was removed by `remove` while waiting.
+            if is_same_file(&file, &path)? {
+                return Ok(file);
+            }
+        }
+    }
+
     /// Load file from cache, if the file not found or expired, return [`None`]
     pub fn load(&self) -> std::result::Result<Option<Vec<u8>>, CacheError> {
+        check_dir(&cache_dir())?;
         let path = self.get_path();
         if path.exists() {
             let metadata = std::fs::metadata(&path)?;
@@ -42,16 +261,70 @@ impl Cache {
         Ok(None)
     }
 
+    /// Load file from cache even if it has expired, if the file not found,
+    /// return [`None`]
+    pub fn load_stale(&self) -> std::result::Result<Option<Vec<u8>>, CacheError> {
+        check_dir(&cache_dir())?;
+        let path = self.get_path();
+        if path.exists() {
+            return Ok(Some(std::fs::read(path)?));
+        }
+        Ok(None)
+    }
+
+    /// Load the validators saved with the cache file
+    pub fn load_validators(&self) -> std::result::Result<Validators, CacheError> {
+        check_dir(&cache_dir())?;
+        let path = self.get_meta_path();
+        let mut validators = Validators::default();
+        if path.exists() {
+            for line in std::fs::read_to_string(path)?.lines() {
+                match line.split_once(": ") {
+                    Some(("etag", etag)) => validators.etag = Some(etag.to_string()),
+                    Some(("last-modified", date)) => {
+                        validators.last_modified = Some(date.to_string())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(validators)
+    }
+
+    /// Whether the cache file was saved by [`Cache::save_builtin`]
+    pub fn is_builtin(&self) -> std::result::Result<bool, CacheError> {
+        check_dir(&cache_dir())?;
+        let path = self.get_meta_path();
+        if path.exists() {
+            return Ok(std::fs::read_to_string(path)?
+                .lines()
+                .any(|line| line == "builtin: true"));
+        }
+        Ok(false)
+    }
+
     pub fn save_str<'a>(&self, s: &'a str) -> std::result::Result<&'a str, CacheError> {
         self.save(s.as_bytes())?;
         Ok(s)
     }
 
-    #[allow(unused)]
+    /// Remove the cache file, its validators and its lock file. Waits for other
+    /// processes holding the lock, so it must not be called while holding it
     pub fn remove(&self) -> std::result::Result<(), CacheError> {
-        let path = self.get_path();
-        if path.exists() {
-            std::fs::remove_file(path)?;
+        let lock = self.lock()?;
+        self.remove_data()?;
+        // Removed while locked, so others waiting for it retry on a new one.
+        std::fs::remove_file(self.get_lock_path())?;
+        drop(lock);
+        Ok(())
+    }
+
+    /// Remove the cache file and its validators, keeping the lock file
+    pub(crate) fn remove_data(&self) -> std::result::Result<(), CacheError> {
+        for path in [self.get_path(), self.get_meta_path()] {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
         }
         Ok(())
     }
@@ -63,6 +336,7 @@ mod tests {
 
     #[test]
     fn test_cache() {
+        use_test_cache_dir();
         let cache = Cache::new("test", Duration::from_millis(20));
         let path = cache.get_path();
         assert!(!path.exists());
@@ -73,4 +347,87 @@ mod tests {
         assert!(cache.load().unwrap().is_none());
         cache.remove().unwrap();
     }
+
+    #[test]
+    fn test_lock() {
+        use_test_cache_dir();
+        let cache = Cache::new("test-lock", Duration::ZERO);
+        let lock = cache.lock().unwrap();
+        let path = cache_dir().join("test-lock.lock");
+        let other = File::open(&path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+
+        // A lock file removed while waiting for it is not used.
+        let waiter = std::thread::spawn(move || {
+            let cache = Cache::new("test-lock", Duration::ZERO);
+            let lock = cache.lock().unwrap();
+            assert!(is_same_file(&lock, &cache.get_lock_path()).unwrap());
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        std::fs::remove_file(&path).unwrap();
+        drop(other);
+        waiter.join().unwrap();
+        cache.remove().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = temp_dir().join("chnroutes-write-atomic-test");
+        create_dir(&dir).unwrap();
+        let path = dir.join("data");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // no temporary file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validators() {
+        use_test_cache_dir();
+        let cache = Cache::new("test-validators", Duration::ZERO);
+        let validators = Validators {
+            etag: Some(r#""5f3a-62b1""#.to_string()),
+            last_modified: Some("Sun, 15 Dec 2024 03:10:01 GMT".to_string()),
+        };
+        cache.save_with_validators(b"test", &validators).unwrap();
+        assert_eq!(cache.load().unwrap(), None);
+        assert_eq!(cache.load_stale().unwrap().unwrap(), b"test");
+        assert_eq!(cache.load_validators().unwrap(), validators);
+        cache.save(b"test").unwrap();
+        assert!(cache.load_validators().unwrap().is_empty());
+        assert!(!cache.is_builtin().unwrap());
+        cache.save_builtin(b"test").unwrap();
+        assert!(cache.is_builtin().unwrap());
+        assert!(cache.load_validators().unwrap().is_empty());
+        cache.save_with_validators(b"test", &validators).unwrap();
+        assert!(!cache.is_builtin().unwrap());
+        cache.remove().unwrap();
+        assert_eq!(cache.load_stale().unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = temp_dir().join("chnroutes-cache-dir-test");
+        let dir = root.join("chnroutes");
+        create_dir(&dir).unwrap();
+        create_dir(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // an existing directory others can write to is refused
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let err = create_dir(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(check_dir(&dir).is_err());
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(create_dir(&dir).is_ok());
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
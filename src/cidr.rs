@@ -0,0 +1,603 @@
+//! Helpers to convert between address ranges and CIDR blocks, and to operate
+//! on CIDR lists.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use ipnet::IpNet;
+
+use crate::{Error, Result};
+
+/// Address families to use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Family {
+    V4,
+    V6,
+    #[default]
+    Both,
+}
+
+impl Family {
+    /// Whether `net` is of this family.
+    pub fn contains(&self, net: &IpNet) -> bool {
+        match self {
+            Self::V4 => net.addr().is_ipv4(),
+            Self::V6 => net.addr().is_ipv6(),
+            Self::Both => true,
+        }
+    }
+}
+
+impl FromStr for Family {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "v4" | "ipv4" | "4" => Ok(Self::V4),
+            "v6" | "ipv6" | "6" => Ok(Self::V6),
+            "both" => Ok(Self::Both),
+            _ => Err(Error::InvalidFamily(s.to_string())),
+        }
+    }
+}
+
+/// Convert an address to its integer value.
+pub(crate) fn to_u128(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+/// Convert an integer value back to an address of the given family.
+pub(crate) fn from_u128(value: u128, ipv6: bool) -> IpAddr {
+    if ipv6 {
+        IpAddr::V6(Ipv6Addr::from(value))
+    } else {
+        IpAddr::V4(Ipv4Addr::from(value as u32))
+    }
+}
+
+/// Convert a CIDR block to its inclusive integer range.
+pub(crate) fn to_range(net: &IpNet) -> (u128, u128) {
+    (to_u128(net.network()), to_u128(net.broadcast()))
+}
+
+/// Get the sorted inclusive ranges of one family in `ips`, with overlapping and
+/// adjacent ranges merged.
+pub(crate) fn merged_ranges(ips: &[IpNet], ipv6: bool) -> Vec<(u128, u128)> {
+    let mut ranges = ips
+        .iter()
+        .filter(|net| net.addr().is_ipv6() == ipv6)
+        .map(to_range)
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Convert inclusive ranges of one family back to a CIDR list.
+pub(crate) fn ranges_to_cidrs(ranges: &[(u128, u128)], ipv6: bool) -> Vec<IpNet> {
+    ranges
+        .iter()
+        .flat_map(|&(start, end)| decompose(start, end, ipv6))
+        .collect()
+}
+
+/// Merge adjacent and overlapping prefixes and drop covered ones, returning the
+/// minimal sorted CIDR list covering the same addresses. IPv4 blocks come
+/// first.
+pub fn aggregate(ips: &[IpNet]) -> Vec<IpNet> {
+    let mut result = ranges_to_cidrs(&merged_ranges(ips, false), false);
+    result.extend(ranges_to_cidrs(&merged_ranges(ips, true), true));
+    result
+}
+
+/// Get the addresses in `a` but not in `b`, as a minimal sorted CIDR list.
+pub fn difference(a: &[IpNet], b: &[IpNet]) -> Vec<IpNet> {
+    let mut result = vec![];
+    for ipv6 in [false, true] {
+        let b = merged_ranges(b, ipv6);
+        let mut ranges = vec![];
+        let mut j = 0;
+        for (mut start, end) in merged_ranges(a, ipv6) {
+            while j < b.len() && b[j].1 < start {
+                j += 1;
+            }
+            let mut covered = false;
+            for &(b_start, b_end) in b[j..].iter().take_while(|r| r.0 <= end) {
+                if b_start > start {
+                    ranges.push((start, b_start - 1));
+                }
+                if b_end >= end {
+                    covered = true;
+                    break;
+                }
+                start = b_end + 1;
+            }
+            if !covered {
+                ranges.push((start, end));
+            }
+        }
+        result.extend(ranges_to_cidrs(&ranges, ipv6));
+    }
+    result
+}
+
+/// Get the addresses in both `a` and `b`, as a minimal sorted CIDR list.
+pub fn intersection(a: &[IpNet], b: &[IpNet]) -> Vec<IpNet> {
+    difference(a, &difference(a, b))
+}
+
+/// Count the distinct addresses of one family in `ips`. `::/0` saturates at
+/// [`u128::MAX`].
+pub fn address_count(ips: &[IpNet], ipv6: bool) -> u128 {
+    merged_ranges(ips, ipv6)
+        .iter()
+        .fold(0u128, |sum, (start, end)| {
+            sum.saturating_add((end - start).saturating_add(1))
+        })
+}
+
+/// Private, reserved and special-purpose ranges that are never reachable on
+/// the internet.
+pub const RESERVED: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "::ffff:0:0/96",
+    "100::/64",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+/// Networks that are local to the site and should never go through the tunnel:
+/// RFC 1918 private ranges, CGNAT, link-local, ULA and multicast.
+pub const LOCAL_NETWORKS: &[&str] = &[
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "224.0.0.0/4",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+/// RFC 1918 private ranges, which the export scripts route directly by
+/// default.
+pub const PRIVATE_NETWORKS: &[&str] = &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"];
+
+/// Link-local and multicast ranges of [`LOCAL_NETWORKS`]. They are reached on
+/// the link rather than through a gateway, so they are never added as routes.
+pub const ON_LINK_NETWORKS: &[&str] = &["169.254.0.0/16", "224.0.0.0/4", "fe80::/10", "ff00::/8"];
+
+/// Parse [`LOCAL_NETWORKS`].
+pub fn local_networks() -> Vec<IpNet> {
+    LOCAL_NETWORKS.iter().map(|x| x.parse().unwrap()).collect()
+}
+
+/// Parse [`PRIVATE_NETWORKS`].
+pub fn private_networks() -> Vec<IpNet> {
+    PRIVATE_NETWORKS
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect()
+}
+
+/// Parse [`ON_LINK_NETWORKS`].
+pub fn on_link_networks() -> Vec<IpNet> {
+    ON_LINK_NETWORKS
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect()
+}
+
+/// Get everything in `0.0.0.0/0` and `::/0` that is neither in `ips` nor in
+/// [`RESERVED`], as a minimal sorted CIDR list.
+///
+/// This is what should go through the tunnel when `ips` should not, e.g. for
+/// WireGuard `AllowedIPs`.
+pub fn complement(ips: &[IpNet]) -> Vec<IpNet> {
+    let mut excluded = ips.to_vec();
+    excluded.extend(RESERVED.iter().map(|x| x.parse::<IpNet>().unwrap()));
+    difference(
+        &["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+        &excluded,
+    )
+}
+
+/// Split the inclusive address range `start..=end` into the minimal list of
+/// CIDR blocks covering exactly that range.
+///
+/// Returns an empty list if `start` and `end` are of different families or
+/// `start > end`.
+pub fn range_to_cidrs(start: IpAddr, end: IpAddr) -> Vec<IpNet> {
+    if start.is_ipv4() != end.is_ipv4() {
+        return vec![];
+    }
+    decompose(to_u128(start), to_u128(end), start.is_ipv6())
+}
+
+/// Split an inclusive integer range into CIDR blocks, greedily taking the
+/// largest block that is aligned on the current start and fits in the range.
+pub(crate) fn decompose(mut start: u128, end: u128, ipv6: bool) -> Vec<IpNet> {
+    let bits: u32 = if ipv6 { 128 } else { 32 };
+    let mut result = vec![];
+    while start <= end {
+        let align = start.trailing_zeros().min(bits);
+        // floor(log2(end - start + 1)), without overflowing on the full range
+        let fit = match (end - start).checked_add(1) {
+            Some(count) => 127 - count.leading_zeros(),
+            None => 128,
+        };
+        let host_bits = align.min(fit);
+        result.push(IpNet::new(from_u128(start, ipv6), (bits - host_bits) as u8).unwrap());
+        let last = start + u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+        if last >= end {
+            break;
+        }
+        start = last + 1;
+    }
+    result
+}
+
+/// Addresses covered by a summarized CIDR list, for one family.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Addresses covered by the original list.
+    pub original: u128,
+    /// Addresses covered by the summarized list but not by the original one.
+    pub extra: u128,
+}
+
+impl Coverage {
+    /// The over-coverage in percent of the original addresses.
+    pub fn percent(&self) -> f64 {
+        if self.original == 0 {
+            return 0.0;
+        }
+        self.extra as f64 / self.original as f64 * 100.0
+    }
+}
+
+/// Result of [`summarize`].
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub ips: Vec<IpNet>,
+    pub v4: Coverage,
+    pub v6: Coverage,
+}
+
+/// Mask of the lowest `host_bits` bits.
+fn host_mask(host_bits: u32) -> u128 {
+    u128::MAX.checked_shr(128 - host_bits).unwrap_or(0)
+}
+
+/// Number of addresses in a block, saturating on `::/0`.
+fn block_size(host_bits: u32) -> u128 {
+    host_mask(host_bits).saturating_add(1)
+}
+
+/// A node of the binary prefix trie used by [`summarize`].
+struct Node {
+    start: u128,
+    host_bits: u32,
+    family: usize,
+    parent: Option<usize>,
+    children: [Option<usize>; 2],
+    /// Whether this node is a route, either from the input or widened.
+    route: bool,
+    /// Routes in this subtree.
+    routes: usize,
+    /// Addresses covered by the routes in this subtree.
+    covered: u128,
+}
+
+/// Widen prefixes until at most `max_routes` remain, greedily choosing the
+/// widening that swallows the fewest addresses not in `ips` per saved route.
+///
+/// The over-coverage of each family is weighted by the family's original
+/// coverage, so IPv4 and IPv6 widenings can be compared. At least one prefix
+/// per family is kept, so the result may exceed `max_routes` if it is smaller
+/// than the number of families in `ips`.
+pub fn summarize(ips: &[IpNet], max_routes: usize) -> Summary {
+    // build a trie of the aggregated blocks, parents always come before children
+    let mut nodes: Vec<Node> = [32, 128]
+        .into_iter()
+        .enumerate()
+        .map(|(family, host_bits)| Node {
+            start: 0,
+            host_bits,
+            family,
+            parent: None,
+            children: [None, None],
+            route: false,
+            routes: 0,
+            covered: 0,
+        })
+        .collect();
+    for net in aggregate(ips) {
+        let family = net.addr().is_ipv6() as usize;
+        let (start, host_bits) = (
+            to_u128(net.network()),
+            (net.max_prefix_len() - net.prefix_len()) as u32,
+        );
+        let mut index = family;
+        while nodes[index].host_bits > host_bits {
+            let child_bits = nodes[index].host_bits - 1;
+            let bit = ((start >> child_bits) & 1) as usize;
+            index = match nodes[index].children[bit] {
+                Some(child) => child,
+                None => {
+                    let child = Node {
+                        start: start & !host_mask(child_bits),
+                        host_bits: child_bits,
+                        family,
+                        parent: Some(index),
+                        children: [None, None],
+                        route: false,
+                        routes: 0,
+                        covered: 0,
+                    };
+                    nodes.push(child);
+                    nodes[index].children[bit] = Some(nodes.len() - 1);
+                    nodes.len() - 1
+                }
+            };
+        }
+        nodes[index].route = true;
+        nodes[index].routes = 1;
+        nodes[index].covered = block_size(host_bits);
+    }
+    for index in (2..nodes.len()).rev() {
+        let (routes, covered, parent) = (
+            nodes[index].routes,
+            nodes[index].covered,
+            nodes[index].parent.unwrap(),
+        );
+        let parent = &mut nodes[parent];
+        parent.routes += routes;
+        parent.covered = parent.covered.saturating_add(covered);
+    }
+    let original = [nodes[0].covered, nodes[1].covered];
+
+    // Cost of widening a node to a single route. Scores are non-negative, so
+    // their bits sort in the same order as the values.
+    let score = |node: &Node| {
+        let extra = block_size(node.host_bits).saturating_sub(node.covered) as f64;
+        (extra / original[node.family] as f64 / (node.routes - 1) as f64).to_bits()
+    };
+    let mut heap = nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.routes > 1)
+        .map(|(index, node)| Reverse((score(node), index)))
+        .collect::<BinaryHeap<_>>();
+    while nodes[0].routes + nodes[1].routes > max_routes {
+        let Some(Reverse((node_score, index))) = heap.pop() else {
+            break;
+        };
+        // skip stale entries and nodes already inside a widened route
+        let mut ancestor = Some(index);
+        let mut widened = false;
+        while let Some(i) = ancestor {
+            widened |= nodes[i].route;
+            ancestor = nodes[i].parent;
+        }
+        if widened || nodes[index].routes < 2 || score(&nodes[index]) != node_score {
+            continue;
+        }
+        let saved = nodes[index].routes - 1;
+        let extra = block_size(nodes[index].host_bits) - nodes[index].covered;
+        nodes[index].route = true;
+        nodes[index].routes = 1;
+        nodes[index].covered += extra;
+        let mut ancestor = nodes[index].parent;
+        while let Some(i) = ancestor {
+            nodes[i].routes -= saved;
+            nodes[i].covered = nodes[i].covered.saturating_add(extra);
+            if nodes[i].routes > 1 {
+                heap.push(Reverse((score(&nodes[i]), i)));
+            }
+            ancestor = nodes[i].parent;
+        }
+    }
+
+    // collect the routes in order
+    let mut ips = vec![];
+    let mut extra = [0u128; 2];
+    let mut stack = vec![1, 0];
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+        if node.route {
+            let bits = if node.family == 1 { 128 } else { 32 };
+            ips.push(
+                IpNet::new(
+                    from_u128(node.start, node.family == 1),
+                    (bits - node.host_bits) as u8,
+                )
+                .unwrap(),
+            );
+            extra[node.family] += block_size(node.host_bits);
+        } else {
+            stack.extend(node.children.iter().rev().flatten());
+        }
+    }
+    let coverage = |family: usize| Coverage {
+        original: original[family],
+        extra: extra[family] - original[family],
+    };
+    Summary {
+        ips,
+        v4: coverage(0),
+        v6: coverage(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn nets(s: &[&str]) -> Vec<IpNet> {
+        s.iter().map(|x| IpNet::from_str(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_range_to_cidrs() {
+        let ip = |s: &str| IpAddr::from_str(s).unwrap();
+        assert_eq!(
+            range_to_cidrs(ip("1.0.1.0"), ip("1.0.1.255")),
+            nets(&["1.0.1.0/24"])
+        );
+        // 768 addresses
+        assert_eq!(
+            range_to_cidrs(ip("1.0.0.0"), ip("1.0.2.255")),
+            nets(&["1.0.0.0/23", "1.0.2.0/24"])
+        );
+        // unaligned start
+        assert_eq!(
+            range_to_cidrs(ip("1.0.1.0"), ip("1.0.4.255")),
+            nets(&["1.0.1.0/24", "1.0.2.0/23", "1.0.4.0/24"])
+        );
+        assert_eq!(
+            range_to_cidrs(ip("0.0.0.0"), ip("255.255.255.255")),
+            nets(&["0.0.0.0/0"])
+        );
+        assert_eq!(
+            range_to_cidrs(ip("::"), ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff")),
+            nets(&["::/0"])
+        );
+        assert_eq!(
+            range_to_cidrs(
+                ip("2001:250::"),
+                ip("2001:252:ffff:ffff:ffff:ffff:ffff:ffff")
+            ),
+            nets(&["2001:250::/31", "2001:252::/32"])
+        );
+        assert!(range_to_cidrs(ip("1.0.0.1"), ip("1.0.0.0")).is_empty());
+        assert!(range_to_cidrs(ip("1.0.0.0"), ip("::")).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let ips = nets(&[
+            "1.0.1.0/24",
+            "1.0.2.0/23",
+            "1.0.0.0/24",
+            "1.0.2.128/25",
+            "2001:250::/32",
+            "1.0.4.0/24",
+            "2001:251::/32",
+            "10.0.0.0/8",
+        ]);
+        assert_eq!(
+            aggregate(&ips),
+            nets(&["1.0.0.0/22", "1.0.4.0/24", "10.0.0.0/8", "2001:250::/31"])
+        );
+        assert_eq!(
+            aggregate(&nets(&[
+                "255.255.255.254/31",
+                "255.255.255.252/31",
+                "0.0.0.0/1",
+                "128.0.0.0/1"
+            ])),
+            nets(&["0.0.0.0/0"])
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let ips = nets(&[
+            "1.0.0.0/24",
+            "1.0.2.0/24",
+            "1.0.4.0/24",
+            "1.0.6.0/23",
+            "1.1.0.0/16",
+            "2001:250::/32",
+            "2001:252::/32",
+        ]);
+        let summary = summarize(&ips, 7);
+        assert_eq!(summary.ips, aggregate(&ips));
+        assert_eq!(summary.v4.extra, 0);
+
+        // widening v4 is much cheaper than v6 relative to the coverage
+        let summary = summarize(&ips, 4);
+        assert_eq!(
+            summary.ips,
+            nets(&["1.0.0.0/21", "1.1.0.0/16", "2001:250::/32", "2001:252::/32"])
+        );
+        assert_eq!(summary.v4.original, 256 * 261);
+        assert_eq!(summary.v4.extra, 256 * 3);
+        assert_eq!(summary.v6.extra, 0);
+
+        let summary = summarize(&ips, 2);
+        assert_eq!(summary.v6.extra, 1 << 97);
+        assert_eq!(summary.v6.percent(), 100.0);
+
+        let summary = summarize(&ips, 0);
+        assert_eq!(summary.ips, nets(&["1.0.0.0/15", "2001:250::/30"]));
+    }
+
+    #[test]
+    fn test_difference() {
+        assert_eq!(
+            difference(
+                &nets(&["1.0.0.0/22", "2.0.0.0/8", "2001:250::/31"]),
+                &nets(&["1.0.1.0/24", "1.0.3.128/25", "2.0.0.0/7", "2001:251::/32"])
+            ),
+            nets(&["1.0.0.0/24", "1.0.2.0/24", "1.0.3.0/25", "2001:250::/32"])
+        );
+    }
+
+    #[test]
+    fn test_intersection() {
+        assert_eq!(
+            intersection(
+                &nets(&["1.0.0.0/22", "2.0.0.0/8", "2001:250::/31"]),
+                &nets(&["1.0.1.0/24", "1.0.3.128/25", "2.0.0.0/7", "2001:251::/32"])
+            ),
+            nets(&["1.0.1.0/24", "1.0.3.128/25", "2.0.0.0/8", "2001:251::/32"])
+        );
+        assert!(intersection(&nets(&["1.0.0.0/24"]), &nets(&["::/0"])).is_empty());
+    }
+
+    #[test]
+    fn test_complement() {
+        let ips = nets(&["1.0.0.0/8", "2001:250::/32"]);
+        let result = complement(&ips);
+        assert!(result.contains(&"2.0.0.0/7".parse().unwrap()));
+        assert!(result.contains(&"2001:251::/32".parse().unwrap()));
+        for net in ips.iter().chain(&nets(RESERVED)) {
+            assert!(result.iter().all(|x| !x.contains(net) && !net.contains(x)));
+        }
+        assert_eq!(complement(&result), ips);
+    }
+}
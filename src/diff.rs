@@ -0,0 +1,100 @@
+//! Compare two snapshots of an IP list, such as the cached data and freshly
+//! fetched data.
+
+use ipnet::IpNet;
+use serde::Serialize;
+
+use crate::cidr;
+
+/// Address counts of one family in two snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FamilyDiff {
+    /// Addresses in the old snapshot.
+    pub old: u128,
+    /// Addresses in the new snapshot.
+    pub new: u128,
+    /// Addresses only in the new snapshot.
+    pub added: u128,
+    /// Addresses only in the old snapshot.
+    pub removed: u128,
+}
+
+impl FamilyDiff {
+    /// The net change of addresses, saturating at the bounds of [`i128`].
+    pub fn change(&self) -> i128 {
+        let added = i128::try_from(self.added).unwrap_or(i128::MAX);
+        let removed = i128::try_from(self.removed).unwrap_or(i128::MAX);
+        added.saturating_sub(removed)
+    }
+}
+
+/// Difference between two snapshots, see [`diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    /// Prefixes covering the addresses only in the new snapshot.
+    pub added: Vec<IpNet>,
+    /// Prefixes covering the addresses only in the old snapshot.
+    pub removed: Vec<IpNet>,
+    pub v4: FamilyDiff,
+    pub v6: FamilyDiff,
+}
+
+impl Diff {
+    /// Whether both snapshots cover the same addresses.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Compare the addresses of two snapshots. Prefixes are compared by the
+/// addresses they cover, so splitting or merging them is not a change.
+pub fn diff(old: &[IpNet], new: &[IpNet]) -> Diff {
+    let added = cidr::difference(new, old);
+    let removed = cidr::difference(old, new);
+    let family = |ipv6| FamilyDiff {
+        old: cidr::address_count(old, ipv6),
+        new: cidr::address_count(new, ipv6),
+        added: cidr::address_count(&added, ipv6),
+        removed: cidr::address_count(&removed, ipv6),
+    };
+    Diff {
+        v4: family(false),
+        v6: family(true),
+        added,
+        removed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let nets =
+            |s: &[&str]| -> Vec<IpNet> { s.iter().map(|x| IpNet::from_str(x).unwrap()).collect() };
+        let old = nets(&["1.0.0.0/23", "1.0.4.0/24", "2001:250::/31"]);
+        let new = nets(&["1.0.0.0/24", "1.0.1.0/24", "1.0.8.0/21", "2001:250::/32"]);
+        let changes = diff(&old, &new);
+        assert_eq!(changes.added, nets(&["1.0.8.0/21"]));
+        assert_eq!(changes.removed, nets(&["1.0.4.0/24", "2001:251::/32"]));
+        assert_eq!(
+            changes.v4,
+            FamilyDiff {
+                old: 768,
+                new: 2560,
+                added: 2048,
+                removed: 256
+            }
+        );
+        assert_eq!(changes.v4.change(), 1792);
+        assert_eq!(changes.v6.change(), -(1 << 96));
+        assert!(diff(
+            &old,
+            &nets(&["1.0.0.0/24", "1.0.1.0/24", "1.0.4.0/24", "2001:250::/31"])
+        )
+        .is_empty());
+    }
+}
@@ -11,13 +11,37 @@ pub enum Error {
     ExecError(#[from] ExecError),
     #[error("Invalid target")]
     InvalidTarget,
+    #[error("Invalid source")]
+    InvalidSource,
+    #[error("Invalid source expression: {0}")]
+    InvalidExpression(String),
+    #[error("Unsupported operation: {0}")]
+    Unsupported(&'static str),
     #[error("Route operation error: {0}")]
     RouteOpError(#[from] RouteOpError),
+    #[error("Invalid address family: {0}")]
+    InvalidFamily(String),
+    #[error("Invalid record status: {0}")]
+    InvalidStatus(String),
+    #[error("Parse error at line {line}: {text:?}")]
+    ParseError { line: usize, text: String },
+    #[error("Checksum mismatch of {url}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Sanity check failed: {0}")]
+    SanityCheckFailed(String),
+    #[error("Decode error: {0}")]
+    DecodeError(String),
+    #[error("MaxMind DB error: {0}")]
+    MaxMindDbError(#[from] maxminddb::MaxMindDBError),
 }
 
 #[derive(Error, Debug)]
 pub enum CacheError {
-    #[error("")]
+    #[error("{0}")]
     IOError(#[from] std::io::Error),
 }
 
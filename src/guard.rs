@@ -0,0 +1,111 @@
+//! Sanity checks on freshly fetched data, so that a captive portal page or a
+//! truncated file never replaces good data.
+
+use std::sync::RwLock;
+
+use ipnet::IpNet;
+
+use crate::{cidr, Error, Result};
+
+/// Limits the prefixes of freshly fetched data must stay within to replace the
+/// previous data. They apply to the CN prefixes of APNIC, chnroutes2 and
+/// default geoip data, and to all prefixes of the other RIRs, which hold few CN
+/// prefixes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Guards {
+    /// Minimum number of prefixes.
+    pub min_prefixes: usize,
+    /// Maximum change of the IPv4 address total from the previous data, in
+    /// percent, or [`None`] for no limit.
+    pub max_change: Option<f64>,
+}
+
+impl Guards {
+    pub const DEFAULT: Self = Self {
+        min_prefixes: 1000,
+        max_change: Some(10.0),
+    };
+
+    /// Check the prefixes of fetched data against the ones of the previous
+    /// data, if there is some.
+    pub fn check(&self, ips: &[IpNet], previous: Option<&[IpNet]>) -> Result<()> {
+        if ips.len() < self.min_prefixes {
+            return Err(Error::SanityCheckFailed(format!(
+                "only {} prefixes, expected at least {}",
+                ips.len(),
+                self.min_prefixes
+            )));
+        }
+        if let (Some(max_change), Some(previous)) = (self.max_change, previous) {
+            let old = cidr::address_count(previous, false) as f64;
+            let new = cidr::address_count(ips, false) as f64;
+            let change = (new - old).abs() / old.max(1.0) * 100.0;
+            if change > max_change {
+                return Err(Error::SanityCheckFailed(format!(
+                    "IPv4 addresses changed by {change:.1}% from {old} to {new}, more than \
+                     {max_change}%"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Guards {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static GUARDS: RwLock<Guards> = RwLock::new(Guards::DEFAULT);
+
+/// Set the guards applied to fetched data, [`Guards::DEFAULT`] by default.
+pub fn set_guards(guards: Guards) {
+    *GUARDS.write().unwrap() = guards;
+}
+
+/// Get the guards applied to fetched data.
+pub fn guards() -> Guards {
+    *GUARDS.read().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let nets =
+            |s: &[&str]| -> Vec<IpNet> { s.iter().map(|x| IpNet::from_str(x).unwrap()).collect() };
+        let guards = Guards {
+            min_prefixes: 2,
+            max_change: Some(10.0),
+        };
+        let previous = nets(&["1.0.0.0/16", "1.1.0.0/16"]);
+        assert!(guards
+            .check(
+                &nets(&["1.0.0.0/16", "1.1.0.0/17", "1.1.128.0/18", "1.1.192.0/19"]),
+                Some(&previous)
+            )
+            .is_ok());
+        assert!(guards
+            .check(&nets(&["1.0.0.0/16", "1.1.0.0/17"]), None)
+            .is_ok());
+        // halved
+        assert!(matches!(
+            guards.check(&nets(&["1.0.0.0/16", "1.1.0.0/32"]), Some(&previous)),
+            Err(Error::SanityCheckFailed(_))
+        ));
+        assert!(matches!(
+            guards.check(&nets(&["1.0.0.0/15"]), None),
+            Err(Error::SanityCheckFailed(_))
+        ));
+        let guards = Guards {
+            min_prefixes: 0,
+            max_change: None,
+        };
+        assert!(guards.check(&[], Some(&previous)).is_ok());
+    }
+}
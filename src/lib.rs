@@ -1,17 +1,41 @@
 pub mod cache;
+pub mod cidr;
+pub mod diff;
 pub mod error;
+pub mod guard;
+pub mod lookup;
+pub mod options;
 pub mod route_op;
 pub mod source;
+pub mod stats;
 pub mod target;
 
 pub use error::{Error, Result};
-pub use source::Source;
+pub use options::Options;
+pub use source::{composite::Composite, IpSource, Source};
 pub use target::Target;
 
-pub async fn up(source: &Source) -> Result<()> {
-    Ok(route_op::add_routes(&source.get_cn_ips()?).await?)
+/// Add routes to the IP list of `source` through the default gateway.
+///
+/// [`Options::complement`] is not supported, since the complement should go
+/// through the tunnel instead.
+pub async fn up(source: &(impl IpSource + ?Sized), options: &Options) -> Result<()> {
+    reject_complement(options)?;
+    Ok(route_op::add_routes(&options.get_ips(source)?, options.family).await?)
 }
 
-pub async fn down(source: &Source) -> Result<()> {
-    Ok(route_op::del_routes(&source.get_cn_ips()?).await?)
+/// Remove the routes added by [`up`].
+pub async fn down(source: &(impl IpSource + ?Sized), options: &Options) -> Result<()> {
+    reject_complement(options)?;
+    Ok(route_op::del_routes(&options.get_ips(source)?).await?)
+}
+
+fn reject_complement(options: &Options) -> Result<()> {
+    if options.complement {
+        return Err(Error::Unsupported(
+            "complement routes must go through the tunnel, export them with the openvpn, \
+             wireguard or cidr target instead",
+        ));
+    }
+    Ok(())
 }
@@ -0,0 +1,149 @@
+//! Look up which prefix of an IP list an address falls in.
+
+use std::{collections::HashMap, net::IpAddr};
+
+use ipnet::IpNet;
+
+use crate::{
+    cidr,
+    source::apnic::{self, Record, Registry},
+    Result,
+};
+
+/// Longest-prefix-match table over a list of prefixes, each carrying a value,
+/// such as the [`Record`] it came from.
+///
+/// Prefixes are indexed by family, length and network address, so a lookup
+/// takes one hash lookup per prefix length in use.
+#[derive(Debug, Clone)]
+pub struct Lookup<T = ()> {
+    entries: Vec<(IpNet, T)>,
+    /// Index into `entries` by (ipv6, prefix length, network address). The
+    /// first entry of duplicated prefixes wins.
+    index: HashMap<(bool, u8, u128), usize>,
+    /// Prefix lengths in use, longest first, for ipv4 and ipv6.
+    lengths: [Vec<u8>; 2],
+}
+
+impl<T> Lookup<T> {
+    pub fn new(entries: impl IntoIterator<Item = (IpNet, T)>) -> Self {
+        let entries = entries
+            .into_iter()
+            .map(|(net, value)| (net.trunc(), value))
+            .collect::<Vec<_>>();
+        let mut index = HashMap::new();
+        let mut lengths = [vec![], vec![]];
+        for (i, (net, _)) in entries.iter().enumerate() {
+            let ipv6 = net.addr().is_ipv6();
+            index
+                .entry((ipv6, net.prefix_len(), cidr::to_u128(net.network())))
+                .or_insert(i);
+            lengths[ipv6 as usize].push(net.prefix_len());
+        }
+        for lengths in &mut lengths {
+            lengths.sort_unstable_by(|a, b| b.cmp(a));
+            lengths.dedup();
+        }
+        Self {
+            entries,
+            index,
+            lengths,
+        }
+    }
+
+    /// Whether `addr` is in any prefix.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.longest_match(addr).is_some()
+    }
+
+    /// Get the most specific prefix containing `addr`, and its value.
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(&IpNet, &T)> {
+        self.longest_match_net(&IpNet::from(addr))
+    }
+
+    /// Get the most specific prefix containing all of `net`, and its value.
+    pub fn longest_match_net(&self, net: &IpNet) -> Option<(&IpNet, &T)> {
+        let ipv6 = net.addr().is_ipv6();
+        let bits = if ipv6 { 128 } else { 32 };
+        let addr = cidr::to_u128(net.network());
+        self.lengths[ipv6 as usize]
+            .iter()
+            .filter(|&&len| len <= net.prefix_len())
+            .find_map(|&len| {
+                let mask = u128::MAX.checked_shl(u32::from(bits - len)).unwrap_or(0);
+                self.index.get(&(ipv6, len, addr & mask))
+            })
+            .map(|&i| (&self.entries[i].0, &self.entries[i].1))
+    }
+
+    /// Number of prefixes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<IpNet> for Lookup {
+    fn from_iter<I: IntoIterator<Item = IpNet>>(iter: I) -> Self {
+        Self::new(iter.into_iter().map(|net| (net, ())))
+    }
+}
+
+impl Lookup<Record> {
+    /// Build a table of every IP record of the given registries, of any country
+    /// and status.
+    pub fn records(registries: &[Registry]) -> Result<Self> {
+        let mut entries = vec![];
+        for &registry in registries {
+            for record in apnic::fetch_registry_records(registry)? {
+                entries.extend(record.nets().into_iter().map(|net| (net, record.clone())));
+            }
+        }
+        Ok(Self::new(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_longest_match() {
+        let lookup = ["1.0.0.0/8", "1.0.1.0/24", "2001:250::/31", "1.0.1.0/24"]
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (IpNet::from_str(x).unwrap(), i))
+            .collect::<Vec<_>>();
+        let lookup = Lookup::new(lookup);
+        let ip = |x: &str| IpAddr::from_str(x).unwrap();
+        let net = |x: &str| IpNet::from_str(x).unwrap();
+        assert_eq!(
+            lookup.longest_match(ip("1.0.1.1")),
+            Some((&net("1.0.1.0/24"), &1))
+        );
+        assert_eq!(
+            lookup.longest_match(ip("1.2.3.4")),
+            Some((&net("1.0.0.0/8"), &0))
+        );
+        assert_eq!(
+            lookup.longest_match(ip("2001:251::1")),
+            Some((&net("2001:250::/31"), &2))
+        );
+        assert!(!lookup.contains(ip("2.0.0.1")));
+        assert!(!lookup.contains(ip("::1.0.1.1")));
+        assert_eq!(
+            lookup.longest_match_net(&net("1.0.0.0/23")),
+            Some((&net("1.0.0.0/8"), &0))
+        );
+        assert_eq!(lookup.longest_match_net(&net("0.0.0.0/0")), None);
+
+        let lookup = Lookup::from_iter([net("0.0.0.0/0")]);
+        assert!(lookup.contains(ip("255.255.255.255")));
+        assert!(!lookup.contains(ip("::")));
+    }
+}
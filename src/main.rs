@@ -1,7 +1,21 @@
-use std::str::FromStr;
+use std::{
+    net::{IpAddr, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+};
 
+use chnroutes::{
+    cidr::Family,
+    diff::Diff,
+    guard::Guards,
+    lookup::Lookup,
+    source::apnic::{self, RecordFilter, Status},
+    stats::Stats,
+    Composite, IpSource,
+};
 use clap::Parser;
 use colored::Colorize;
+use ipnet::IpNet;
 use log::LevelFilter;
 
 #[inline]
@@ -28,19 +42,111 @@ pub fn log_init_with_default_level(level: LevelFilter) {
 pub struct Cli {
     #[command(subcommand)]
     pub subcommand: Subcommand,
-    /// WIP: source to generate ip rules
+    /// Source to generate ip rules: apnic (default), arin, ripencc, lacnic,
+    /// afrinic, rir (all five RIRs), chnroutes2, geoip[:<url or path>],
+    /// mmdb:<path>, or a CIDR list at file://<path> or http(s)://<url>.
+    /// Sources can be combined with + (union), & (intersection), - (difference)
+    /// and parentheses, like "(apnic + chnroutes2) - file:///block.txt"
     #[arg(short, long)]
     source: Option<String>,
+    /// Country codes to get IPs of, separated by commas
+    #[arg(short, long, value_delimiter = ',', default_value = "CN")]
+    country: Vec<String>,
+    /// Only use RIR records with these statuses, separated by commas:
+    /// allocated, assigned, available, reserved
+    #[arg(long, value_delimiter = ',', value_parser = parse_status)]
+    status: Vec<Status>,
+    /// Only use RIR records delegated on or after this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<u32>,
+    /// Only use RIR records delegated before this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    before: Option<u32>,
+    /// Address families to use: v4, v6 or both (default). With both, a family
+    /// without a default gateway is skipped by `up`
+    #[arg(long, value_parser = parse_family, default_value = "both")]
+    family: Family,
+    /// Use everything except the source IPs and reserved ranges instead, to
+    /// route through the tunnel. Only for export to openvpn, wireguard or cidr
+    #[arg(long)]
+    complement: bool,
+    /// Extra prefixes or addresses to route directly, separated by commas
+    #[arg(long, value_delimiter = ',', value_parser = parse_net)]
+    include: Vec<IpNet>,
+    /// Route local networks directly too: private, CGNAT and ULA ranges, and
+    /// keep link-local and multicast ranges out of --complement
+    #[arg(long)]
+    local: bool,
+    /// Do not route the private ranges directly, which the export scripts do
+    /// by default
+    #[arg(long, conflicts_with_all = ["local", "local_networks"])]
+    no_local: bool,
+    /// Route these local networks directly instead of the default ones of
+    /// --local, separated by commas
+    #[arg(long, value_delimiter = ',', value_parser = parse_net)]
+    local_networks: Vec<IpNet>,
+    /// Prefixes or addresses to force through the tunnel, separated by commas.
+    /// Larger prefixes containing them are split
+    #[arg(long, value_delimiter = ',', value_parser = parse_net)]
+    exclude: Vec<IpNet>,
+    /// Do not merge adjacent and overlapping prefixes before use
+    #[arg(long)]
+    no_aggregate: bool,
+    /// Widen prefixes to use at most this many routes, at the cost of
+    /// routing some non-CN addresses directly
+    #[arg(long)]
+    max_routes: Option<usize>,
+    /// Directory of cache files. Defaults to $CHNROUTES_CACHE_DIR, or
+    /// /var/cache/chnroutes as root, or the user cache directory
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    /// Reject fetched RIR, chnroutes2 or geoip data with fewer prefixes. Only
+    /// CN prefixes count, except for RIRs other than APNIC
+    #[arg(long, default_value_t = Guards::DEFAULT.min_prefixes)]
+    min_prefixes: usize,
+    /// Reject fetched RIR, chnroutes2 or geoip data whose IPv4 addresses,
+    /// counted like --min-prefixes, changed by more than this percentage
+    /// from the previous data, inf to disable
+    #[arg(long, default_value_t = Guards::DEFAULT.max_change.unwrap_or(f64::INFINITY))]
+    max_change: f64,
+}
+
+fn parse_status(s: &str) -> Result<Status, String> {
+    Status::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_family(s: &str) -> Result<Family, String> {
+    Family::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_net(s: &str) -> Result<IpNet, String> {
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| format!("invalid prefix: {s}"))
+}
+
+fn parse_date(s: &str) -> Result<u32, String> {
+    apnic::parse_date(s).ok_or_else(|| format!("invalid date: {s}"))
 }
 
 #[derive(Debug, clap::Subcommand, Clone)]
 pub enum Subcommand {
-    /// Export up and down scripts for windows, mac, linux, android, openvpn
+    /// Export up and down scripts for windows, mac, linux, android, or config
+    /// for openvpn, wireguard, or a plain cidr list
     Export(ExportArgs),
     /// Write IP rules to system route table
     Up,
     /// Remove IP rules from system route table
     Down,
+    /// Look up whether IPs, CIDRs or hostnames are in the IP list, and the
+    /// RIR record they belong to
+    Lookup(LookupArgs),
+    /// Compare two snapshots of the IP list, by default the cached data and
+    /// freshly fetched data. Only a single source can be compared
+    Diff(DiffArgs),
+    /// Show statistics of the IP list and the header of the RIR files
+    Stats,
 }
 
 #[derive(Debug, clap::Args, Clone)]
@@ -50,34 +156,260 @@ pub struct ExportArgs {
     platform: Option<String>,
 }
 
-// TODO: deal with source
+#[derive(Debug, clap::Args, Clone)]
+pub struct LookupArgs {
+    /// IPs, CIDRs or hostnames to look up
+    #[arg(required = true)]
+    targets: Vec<String>,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct DiffArgs {
+    /// File of the old snapshot, instead of the cached data
+    #[arg(long)]
+    old: Option<String>,
+    /// File of the new snapshot, instead of freshly fetched data
+    #[arg(long)]
+    new: Option<String>,
+    /// Print the difference as JSON
+    #[arg(long)]
+    json: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     log_init();
     let cli = Cli::parse();
-    let source = &cli.source;
+    if let Some(dir) = cli.cache_dir {
+        chnroutes::cache::set_cache_dir(dir);
+    }
+    chnroutes::guard::set_guards(Guards {
+        min_prefixes: cli.min_prefixes,
+        max_change: Some(cli.max_change).filter(|x| x.is_finite()),
+    });
+    let source = match cli.source.as_deref() {
+        Some(source) => Composite::from_str(source).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            eprint!("Sources can be combined with +, & and -, and must in ");
+            [
+                "apnic",
+                "arin",
+                "ripencc",
+                "lacnic",
+                "afrinic",
+                "rir",
+                "chnroutes2",
+                "geoip[:<url or path>]",
+                "mmdb:<path>",
+                "file://<path>",
+                "http(s)://<url>",
+            ]
+            .iter()
+            .for_each(|x| eprint!("{}, ", x.green()));
+            eprintln!();
+            std::process::exit(1);
+        }),
+        None => Default::default(),
+    };
+    let options = chnroutes::Options {
+        countries: cli.country,
+        record_filter: RecordFilter {
+            statuses: cli.status,
+            since: cli.since,
+            before: cli.before,
+        },
+        family: cli.family,
+        complement: cli.complement,
+        include: cli.include,
+        local_networks: if cli.no_local {
+            Some(vec![])
+        } else if !cli.local_networks.is_empty() {
+            Some(cli.local_networks)
+        } else if cli.local {
+            Some(chnroutes::cidr::local_networks())
+        } else {
+            None
+        },
+        exclude: cli.exclude,
+        aggregate: !cli.no_aggregate,
+        max_routes: cli.max_routes,
+    };
     match cli.subcommand {
         Subcommand::Export(ExportArgs { platform }) => {
-            export(platform.as_deref(), source.as_deref())
+            export(platform.as_deref(), &source, &options)?
         }
-        Subcommand::Up => chnroutes::up(&Default::default()).await?,
+        Subcommand::Up => chnroutes::up(&source, &options).await?,
         Subcommand::Down => {
-            chnroutes::down(&Default::default()).await?;
+            chnroutes::down(&source, &options).await?;
         }
+        Subcommand::Lookup(LookupArgs { targets }) => lookup(&targets, &source, &options)?,
+        Subcommand::Diff(args) => diff(&args, &source, &options)?,
+        Subcommand::Stats => print_stats(&chnroutes::stats::stats(&source, &options)?),
     }
     Ok(())
 }
 
-pub fn export(platform: Option<&str>, _source: Option<&str>) {
+pub fn export(
+    platform: Option<&str>,
+    source: &Composite,
+    options: &chnroutes::Options,
+) -> chnroutes::Result<()> {
     let target = chnroutes::Target::from_str(platform.unwrap_or_default());
     if let Ok(target) = target {
-        target.export_file(&Default::default()).unwrap();
+        target.export_file(source, options)?;
     } else {
         eprint!("Unknown platform. platform must in ");
-        ["windows", "mac", "linux", "android", "openvpn"]
-            .iter()
-            .for_each(|x| eprint!("{}, ", x.green()));
+        [
+            "windows",
+            "mac",
+            "linux",
+            "android",
+            "openvpn",
+            "wireguard",
+            "cidr",
+        ]
+        .iter()
+        .for_each(|x| eprint!("{}, ", x.green()));
         eprintln!();
         std::process::exit(1);
     }
+    Ok(())
+}
+
+pub fn lookup(
+    targets: &[String],
+    source: &Composite,
+    options: &chnroutes::Options,
+) -> chnroutes::Result<()> {
+    let routes = Lookup::from_iter(options.source_options().get_ips(source)?);
+    let records = Lookup::records(&source.registries())?;
+    for target in targets {
+        let nets = match parse_net(target) {
+            Ok(net) => vec![(target.clone(), net)],
+            // Not an IP or CIDR, resolve it as a hostname.
+            Err(_) => match (target.as_str(), 0).to_socket_addrs() {
+                Ok(addrs) => addrs
+                    .map(|addr| (format!("{target} ({})", addr.ip()), IpNet::from(addr.ip())))
+                    .collect(),
+                Err(e) => {
+                    eprintln!("{target}: {}", format!("cannot resolve: {e}").red());
+                    continue;
+                }
+            },
+        };
+        for (name, net) in nets {
+            match routes.longest_match_net(&net) {
+                Some((prefix, _)) => print!("{name}: {} {prefix}", "in".green()),
+                None => print!("{name}: {}", "not in list".red()),
+            }
+            if let Some((_, record)) = records.longest_match_net(&net) {
+                print!(", record {record}");
+                if let Some(date) = record.date {
+                    print!(", allocated on {}", apnic::format_date(date));
+                }
+            }
+            println!();
+        }
+    }
+    Ok(())
+}
+
+pub fn diff(
+    args: &DiffArgs,
+    source: &Composite,
+    options: &chnroutes::Options,
+) -> chnroutes::Result<()> {
+    let Composite::Source(source) = source else {
+        eprintln!(
+            "Only a single source can be compared, not {}",
+            source.name()
+        );
+        std::process::exit(1);
+    };
+    let old = match &args.old {
+        Some(path) => std::fs::read(path)?,
+        None => source.cached()?.unwrap_or_else(|| {
+            eprintln!("No cached {} data, use --old instead", source.name());
+            std::process::exit(1);
+        }),
+    };
+    let new = match &args.new {
+        Some(path) => std::fs::read(path)?,
+        None => source.fetch()?,
+    };
+    let diff = chnroutes::diff::diff(
+        &options.parse_ips(source, &old)?,
+        &options.parse_ips(source, &new)?,
+    );
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print_diff(&diff);
+    }
+    Ok(())
+}
+
+fn print_diff(diff: &Diff) {
+    for net in &diff.added {
+        println!("{} {net}", "+".green());
+    }
+    for net in &diff.removed {
+        println!("{} {net}", "-".red());
+    }
+    println!(
+        "{} prefixes added, {} removed",
+        diff.added.len(),
+        diff.removed.len()
+    );
+    for (family, counts) in [("IPv4", diff.v4), ("IPv6", diff.v6)] {
+        println!(
+            "{family}: {} -> {} addresses ({:+})",
+            counts.old,
+            counts.new,
+            counts.change()
+        );
+    }
+}
+
+fn print_stats(stats: &Stats) {
+    println!("Records: {}", stats.records);
+    println!(
+        "Prefixes: {} (IPv4 {}, IPv6 {})",
+        stats.prefixes(),
+        stats.v4.prefixes,
+        stats.v6.prefixes
+    );
+    println!("IPv4 addresses: {}", stats.v4.addresses);
+    println!("IPv6 /32 equivalents: {:.2}", stats.ipv6_slash32s());
+    println!("Prefix lengths:");
+    for (family, lengths) in [("IPv4", &stats.v4.lengths), ("IPv6", &stats.v6.lengths)] {
+        for (len, count) in lengths {
+            println!("  {family} /{len}: {count}");
+        }
+    }
+    for header in &stats.headers {
+        print!(
+            "File {}: version {}, serial {}, {} records",
+            header.registry.name(),
+            header.version,
+            header.serial,
+            header.records
+        );
+        if !header.summary.is_empty() {
+            let summary = header
+                .summary
+                .iter()
+                .map(|(kind, count)| format!("{} {count}", kind.name()))
+                .collect::<Vec<_>>();
+            print!(" ({})", summary.join(", "));
+        }
+        if let Some(date) = header.end_date {
+            print!(
+                ", generated on {} ({})",
+                apnic::format_date(date),
+                header.utc_offset
+            );
+        }
+        println!();
+    }
 }
@@ -0,0 +1,251 @@
+use ipnet::IpNet;
+use log::info;
+
+use crate::{
+    cidr::{self, Family},
+    source::{apnic::RecordFilter, IpSource},
+    Result,
+};
+
+/// Options to process the IP list of an [`IpSource`] before it is written to
+/// the route table or exported.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Country codes to get IPs of. Defaults to `CN`.
+    pub countries: Vec<String>,
+    /// Filter on the status and date of records, only for RIR sources.
+    pub record_filter: RecordFilter,
+    /// Address families to use. Both by default.
+    pub family: Family,
+    /// Use everything except the selected IPs and reserved ranges instead, see
+    /// [`cidr::complement`]. These go through the tunnel, like WireGuard
+    /// `AllowedIPs`, so only some [`Target`](crate::Target)s support it.
+    pub complement: bool,
+    /// Extra prefixes to add to the selected IPs, i.e. to route directly. With
+    /// `complement`, they are added before complementing and kept out of the
+    /// result.
+    pub include: Vec<IpNet>,
+    /// Networks that always stay direct, like [`cidr::LOCAL_NETWORKS`]. They
+    /// are added to the selected IPs, or removed from them with
+    /// `complement`. [`cidr::ON_LINK_NETWORKS`] are never added, they only
+    /// matter with `complement`. [`None`] by default, which means
+    /// [`cidr::PRIVATE_NETWORKS`] for the scripts of [`Target`](crate::Target)
+    /// and none otherwise.
+    pub local_networks: Option<Vec<IpNet>>,
+    /// Prefixes to remove from the selected IPs, i.e. to force through the
+    /// tunnel, splitting larger prefixes that contain them. Applied last, so
+    /// summarizing never covers them again, which may leave a few routes more
+    /// than `max_routes`. With `complement`, they are removed before
+    /// complementing instead, so they end up in the result.
+    pub exclude: Vec<IpNet>,
+    /// Merge adjacent and overlapping prefixes and drop covered ones, so fewer
+    /// routes are needed for the same addresses. Enabled by default.
+    pub aggregate: bool,
+    /// Widen prefixes until at most this many routes remain, see
+    /// [`cidr::summarize`].
+    pub max_routes: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            countries: vec!["CN".to_string()],
+            record_filter: RecordFilter::default(),
+            family: Family::Both,
+            complement: false,
+            include: vec![],
+            local_networks: None,
+            exclude: vec![],
+            aggregate: true,
+            max_routes: None,
+        }
+    }
+}
+
+impl Options {
+    /// Get the IP list of `source` and apply the options to it.
+    pub fn get_ips(&self, source: &(impl IpSource + ?Sized)) -> Result<Vec<IpNet>> {
+        Ok(self.apply(source.get_ips(&self.countries, &self.record_filter)?))
+    }
+
+    /// Parse the IP list from raw data of `source` and apply the options to it.
+    pub fn parse_ips(&self, source: &(impl IpSource + ?Sized), data: &[u8]) -> Result<Vec<IpNet>> {
+        Ok(self.apply(source.parse(data, &self.countries, &self.record_filter)?))
+    }
+
+    /// The options without `local_networks`, `include` and `complement`, which
+    /// add or invert addresses, to only select the IPs of the source itself.
+    pub fn source_options(&self) -> Self {
+        Self {
+            complement: false,
+            include: vec![],
+            local_networks: Some(vec![]),
+            ..self.clone()
+        }
+    }
+
+    /// Apply the options to an IP list.
+    pub fn apply(&self, mut ips: Vec<IpNet>) -> Vec<IpNet> {
+        ips.extend_from_slice(&self.include);
+        let local_networks = self.local_networks.as_deref().unwrap_or_default();
+        // Prefixes removed last, so summarizing never covers them again.
+        let removed = if self.complement {
+            if !self.exclude.is_empty() {
+                ips = cidr::difference(&ips, &self.exclude);
+            }
+            ips = cidr::complement(&ips);
+            // Included and local networks stay direct, unless excluded.
+            let mut direct = self.include.clone();
+            direct.extend_from_slice(local_networks);
+            cidr::difference(&direct, &self.exclude)
+        } else {
+            if !local_networks.is_empty() {
+                ips.extend(cidr::difference(local_networks, &cidr::on_link_networks()));
+            }
+            self.exclude.clone()
+        };
+        ips.retain(|net| self.family.contains(net));
+        if self.aggregate {
+            let aggregated = cidr::aggregate(&ips);
+            info!(
+                "Aggregated {} prefixes into {}, saved {} entries.",
+                ips.len(),
+                aggregated.len(),
+                ips.len().saturating_sub(aggregated.len())
+            );
+            ips = aggregated;
+        }
+        if let Some(max_routes) = self.max_routes {
+            let summary = cidr::summarize(&ips, max_routes);
+            info!(
+                "Summarized {} prefixes into {}, over-coverage: {} IPv4 addresses ({:.2}%), {} IPv6 addresses ({:.2}%).",
+                ips.len(),
+                summary.ips.len(),
+                summary.v4.extra,
+                summary.v4.percent(),
+                summary.v6.extra,
+                summary.v6.percent()
+            );
+            ips = summary.ips;
+        }
+        if !removed.is_empty() {
+            ips = cidr::difference(&ips, &removed);
+        }
+        ips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let ips = ["1.0.2.0/24", "1.0.3.0/24", "1.0.2.0/25"]
+            .map(|x| IpNet::from_str(x).unwrap())
+            .to_vec();
+        let base = Options::default();
+        assert_eq!(
+            base.apply(ips.clone()),
+            [IpNet::from_str("1.0.2.0/23").unwrap()]
+        );
+        let options = Options {
+            aggregate: false,
+            ..base.clone()
+        };
+        assert_eq!(options.apply(ips.clone()), ips);
+        let options = Options {
+            max_routes: Some(1),
+            ..base.clone()
+        };
+        assert_eq!(
+            options.apply(vec![
+                IpNet::from_str("1.0.2.0/24").unwrap(),
+                IpNet::from_str("1.0.0.0/24").unwrap()
+            ]),
+            [IpNet::from_str("1.0.0.0/22").unwrap()]
+        );
+        let options = Options {
+            include: vec![IpNet::from_str("1.0.4.0/24").unwrap()],
+            exclude: vec![IpNet::from_str("1.0.2.128/25").unwrap()],
+            ..base.clone()
+        };
+        assert_eq!(
+            options.apply(ips.clone()),
+            ["1.0.2.0/25", "1.0.3.0/24", "1.0.4.0/24"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert!(base.apply(vec![]).is_empty());
+        let options = Options {
+            local_networks: Some(cidr::local_networks()),
+            ..base.clone()
+        };
+        let local = options.apply(vec![]);
+        assert!(local.contains(&IpNet::from_str("192.168.0.0/16").unwrap()));
+        assert!(local.contains(&IpNet::from_str("fc00::/7").unwrap()));
+        // link-local and multicast are never routed through a gateway
+        for net in cidr::on_link_networks() {
+            assert!(local.iter().all(|x| !x.contains(&net) && !net.contains(x)));
+        }
+        let options = Options {
+            complement: true,
+            local_networks: Some(vec![IpNet::from_str("198.19.0.0/16").unwrap()]),
+            ..base.clone()
+        };
+        assert!(options
+            .apply(vec![])
+            .iter()
+            .all(|net| !net.contains(&IpNet::from_str("198.19.1.0/24").unwrap())));
+        let mut options = Options {
+            complement: true,
+            include: vec![IpNet::from_str("1.0.4.0/24").unwrap()],
+            exclude: vec![IpNet::from_str("1.0.2.128/25").unwrap()],
+            ..base.clone()
+        };
+        let tunnel = options.apply(ips.clone());
+        let covered = |tunnel: &[IpNet], s: &str| {
+            let net = IpNet::from_str(s).unwrap();
+            tunnel.iter().any(|x| x.contains(&net))
+        };
+        // included stays direct, excluded goes through the tunnel
+        assert!(!covered(&tunnel, "1.0.4.1/32"));
+        assert!(covered(&tunnel, "1.0.2.128/25"));
+        assert!(!covered(&tunnel, "1.0.2.0/32") && !covered(&tunnel, "1.0.3.0/32"));
+        assert!(covered(&tunnel, "1.0.5.0/24"));
+        // even after summarizing
+        options.max_routes = Some(100);
+        assert!(!covered(&options.apply(ips), "1.0.4.1/32"));
+        let options = Options {
+            family: Family::V6,
+            ..base.clone()
+        };
+        assert_eq!(
+            options.apply(vec![
+                IpNet::from_str("1.0.2.0/24").unwrap(),
+                IpNet::from_str("2001:250::/32").unwrap()
+            ]),
+            [IpNet::from_str("2001:250::/32").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_source_options() {
+        let ips = ["1.0.2.0/24", "1.0.3.0/24"]
+            .map(|x| IpNet::from_str(x).unwrap())
+            .to_vec();
+        let options = Options {
+            complement: true,
+            include: vec![IpNet::from_str("1.0.4.0/24").unwrap()],
+            local_networks: Some(cidr::local_networks()),
+            max_routes: Some(1),
+            ..Default::default()
+        };
+        let source_options = options.source_options();
+        assert_eq!(source_options.max_routes, Some(1));
+        assert_eq!(
+            source_options.apply(ips),
+            [IpNet::from_str("1.0.2.0/23").unwrap()]
+        );
+    }
+}
@@ -8,10 +8,10 @@ use ipnet::IpNet;
 use net_route::{Handle, Route};
 use tokio::sync::OnceCell;
 
-use crate::error::RouteOpError;
+use crate::{cidr::Family, error::RouteOpError};
 pub static GATEWAY: OnceCell<(Option<Ipv4Addr>, Option<Ipv6Addr>)> = OnceCell::const_new();
 pub static INTERFACE_INDEX: OnceLock<u32> = OnceLock::new();
-use log::{error, info};
+use log::{error, info, warn};
 use netdev::get_default_interface;
 use once_fn::once;
 
@@ -45,30 +45,50 @@ pub async fn get_gateway(handle: &Handle) -> Result<(Option<Ipv4Addr>, Option<Ip
     Ok((v4, v6))
 }
 
+/// Get the gateway for routes to ipv4 or ipv6 destinations.
+fn family_gateway(gateway: &(Option<Ipv4Addr>, Option<Ipv6Addr>), ipv4: bool) -> Option<IpAddr> {
+    if ipv4 {
+        return gateway.0.map(IpAddr::from);
+    }
+    #[cfg(not(windows))]
+    {
+        gateway.1.map(IpAddr::from)
+    }
+    #[cfg(windows)]
+    {
+        // on windows, gateway can be ipv4 while destination is ipv6
+        gateway.1.map(IpAddr::from).or(gateway.0.map(IpAddr::from))
+    }
+}
+
+/// Skip the routes of a family without a gateway, with a warning. Fails if
+/// there is no gateway at all.
+fn routes_with_gateway(
+    mut routes: Vec<IpNet>,
+    gateway: &(Option<Ipv4Addr>, Option<Ipv6Addr>),
+) -> Result<Vec<IpNet>> {
+    if gateway.0.is_none() && gateway.1.is_none() {
+        return Err(RouteOpError::NoGatewayError);
+    }
+    for (ipv4, name) in [(true, "IPv4"), (false, "IPv6")] {
+        if family_gateway(gateway, ipv4).is_none()
+            && routes.iter().any(|r| r.addr().is_ipv4() == ipv4)
+        {
+            warn!("No {name} default gateway found, skipping {name} routes.");
+            routes.retain(|r| r.addr().is_ipv4() != ipv4);
+        }
+    }
+    Ok(routes)
+}
+
 /// Add one route entry to routing table.
 pub async fn add_route(handle: &Handle, route: &IpNet) -> Result<()> {
     let gateway = GATEWAY.get_or_try_init(|| get_gateway(handle)).await?;
     let route_item = &Route::new(route.addr(), route.prefix_len())
         // deal with ipv4 and ipv6
-        .with_gateway(if route.addr().is_ipv4() {
-            IpAddr::from(gateway.0.ok_or(RouteOpError::NoGatewayError)?)
-        } else {
-            #[cfg(not(windows))]
-            {
-                IpAddr::from(gateway.1.ok_or(RouteOpError::NoGatewayError)?)
-            }
-            #[cfg(windows)]
-            {
-                // on windows, gateway can be ipv4 while destination is ipv6
-                if let Some(g) = gateway.1 {
-                    IpAddr::from(g)
-                } else if let Some(g) = gateway.0 {
-                    IpAddr::from(g)
-                } else {
-                    return Err(RouteOpError::NoGatewayError);
-                }
-            }
-        })
+        .with_gateway(
+            family_gateway(gateway, route.addr().is_ipv4()).ok_or(RouteOpError::NoGatewayError)?,
+        )
         .with_ifindex(get_interface_index().map_err(RouteOpError::GetInterfaceError)?);
 
     // deal with RouteAlreadyExistsError
@@ -104,11 +124,19 @@ pub async fn del_route(handle: &Handle, route: &IpNet) -> Result<()> {
 }
 
 /// Add multiple routes to routing table.
-pub async fn add_routes(routes: &[IpNet]) -> Result<()> {
-    info!("Adding {} routes...", routes.len());
+///
+/// With [`Family::Both`], routes of a family without a default gateway are
+/// skipped with a warning, instead of failing.
+pub async fn add_routes(routes: &[IpNet], family: Family) -> Result<()> {
     let handle = Box::leak(Box::new(
         Handle::new().map_err(|_| RouteOpError::HandleInitError)?,
     ));
+    let mut routes = routes.to_vec();
+    if family == Family::Both {
+        let gateway = GATEWAY.get_or_try_init(|| get_gateway(handle)).await?;
+        routes = routes_with_gateway(routes, gateway)?;
+    }
+    info!("Adding {} routes...", routes.len());
     let mut futures = routes
         .iter()
         .map(|r| add_route(handle, r))
@@ -171,6 +199,38 @@ pub async fn del_routes(routes: &[IpNet]) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_routes_with_gateway() {
+        let v4 = IpNet::from(IpAddr::from([1, 0, 1, 0]));
+        let v6 = IpNet::from("2001:250::".parse::<IpAddr>().unwrap());
+        let routes = vec![v4, v6];
+        let gateway_v4 = Some(Ipv4Addr::new(192, 168, 1, 1));
+        let gateway_v6 = Some("fe80::1".parse::<Ipv6Addr>().unwrap());
+
+        assert_eq!(
+            routes_with_gateway(routes.clone(), &(gateway_v4, gateway_v6)).unwrap(),
+            routes
+        );
+        // on windows, ipv6 routes can go through the ipv4 gateway
+        let expected = if cfg!(windows) {
+            routes.clone()
+        } else {
+            vec![v4]
+        };
+        assert_eq!(
+            routes_with_gateway(routes.clone(), &(gateway_v4, None)).unwrap(),
+            expected
+        );
+        assert_eq!(
+            routes_with_gateway(routes.clone(), &(None, gateway_v6)).unwrap(),
+            [v6]
+        );
+        assert!(matches!(
+            routes_with_gateway(routes, &(None, None)),
+            Err(RouteOpError::NoGatewayError)
+        ));
+    }
+
     #[tokio::test]
     async fn test_get_gateway() {
         let handle = Handle::new().unwrap();
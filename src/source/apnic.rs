@@ -1,67 +1,602 @@
-use std::{io::Cursor, net::IpAddr, str::FromStr, time::Duration};
+use std::{fmt, io::Cursor, net::IpAddr, str::FromStr, time::Duration};
 
 use ipnet::IpNet;
-use log::{info, warn};
+use log::warn;
 
-use crate::cache::Cache;
+use super::{Checks, Checksum, IpSource, ParseFn};
+use crate::{cidr, Error, Result};
 
-/// Fetch IP data from apnic.net, add it to cache and return the parsed data
-pub fn fetch_ip_data() -> crate::error::Result<Vec<IpNet>> {
-    let cache = Cache::new("apnic", Duration::from_secs(7 * 24 * 60 * 60));
-    if let Some(data) = cache.load()? {
-        info!("Loading data from cache ...");
-        return Ok(parse_ip_data(
-            &String::from_utf8(data).expect("The cache file should be valid UTF-8."),
-        ));
+/// Regional Internet Registries publishing delegated-stats files in the same
+/// pipe-delimited format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registry {
+    Apnic,
+    Arin,
+    RipeNcc,
+    Lacnic,
+    Afrinic,
+}
+
+impl Registry {
+    pub const ALL: [Self; 5] = [
+        Self::Apnic,
+        Self::Arin,
+        Self::RipeNcc,
+        Self::Lacnic,
+        Self::Afrinic,
+    ];
+
+    /// Name of the registry, as used in the registry column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Apnic => "apnic",
+            Self::Arin => "arin",
+            Self::RipeNcc => "ripencc",
+            Self::Lacnic => "lacnic",
+            Self::Afrinic => "afrinic",
+        }
     }
-    info!("Fetching data from apnic.net ...");
-    let client = reqwest::blocking::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()?;
-    let url = "https://ftp.apnic.net/apnic/stats/apnic/delegated-apnic-latest";
-    let data = client.get(url).send().map(|r| r.text());
-    match data {
-        Ok(Ok(data)) => {
-            info!("Fetching data from apnic.net done");
-            cache.save_str(&data)?;
-            Ok(parse_ip_data(&data))
+
+    /// Checksum of the latest delegated-stats file, published next to it.
+    pub(crate) fn checksum(&self) -> Checksum {
+        Checksum::Md5(format!("{}.md5", self.url()))
+    }
+
+    /// Checks on the downloaded file: its checksum, its record counts and the
+    /// guards on its CN records, or on all records for registries other than
+    /// APNIC, which hold few CN records.
+    pub(crate) fn checks(&self) -> Checks {
+        let guarded_ips: ParseFn = match self {
+            Self::Apnic => |data| {
+                let records = parse_checked_records(data)?;
+                Ok(select(&records, &["CN"], &RecordFilter::default()))
+            },
+            _ => |data| {
+                Ok(parse_checked_records(data)?
+                    .iter()
+                    .flat_map(Record::nets)
+                    .collect())
+            },
+        };
+        Checks {
+            checksum: Some(self.checksum()),
+            guarded_ips: Some(guarded_ips),
         }
-        // If the data fetch failed, use the built-in data instead.
-        Ok(Err(e)) | Err(e) => {
-            warn!("Fetching data from apnic.net failed, use built-in apnic data: {e:?}");
-            let compressed_bytes = include_bytes!(concat!(env!("OUT_DIR"), "/apnic.zst"));
-            let de = zstd::stream::decode_all(Cursor::new(compressed_bytes)).unwrap();
-            cache.save(&de)?;
-            Ok(parse_ip_data(
-                &String::from_utf8(de).expect("The cache file should be valid UTF-8."),
-            ))
+    }
+
+    /// Get the registry from the registry column.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.name() == name)
+    }
+
+    /// URL of the latest delegated-stats file. ARIN only publishes the extended
+    /// format.
+    pub fn url(&self) -> &'static str {
+        match self {
+            Self::Apnic => "https://ftp.apnic.net/apnic/stats/apnic/delegated-apnic-latest",
+            Self::Arin => "https://ftp.arin.net/pub/stats/arin/delegated-arin-extended-latest",
+            Self::RipeNcc => "https://ftp.ripe.net/pub/stats/ripencc/delegated-ripencc-latest",
+            Self::Lacnic => "https://ftp.lacnic.net/pub/stats/lacnic/delegated-lacnic-latest",
+            Self::Afrinic => "https://ftp.afrinic.net/pub/stats/afrinic/delegated-afrinic-latest",
         }
     }
 }
 
-/// Parse IP data from str.
-pub fn parse_ip_data(content: &str) -> Vec<IpNet> {
-    content
-        .lines()
-        .map(|line| line.split('|').collect::<Vec<&str>>())
-        .filter(|item| item.len() >= 5)
-        .filter(|item| item[0] == "apnic" && item[1] == "CN" && ["ipv4", "ipv6"].contains(&item[2]))
-        .map(|item| {
-            let prefix_len = if item[2] == "ipv4" {
-                32 - (item[4].parse::<u32>().expect("item[4] must be a number") as f32).log2() as u8
-            } else {
-                item[4].parse::<u8>().expect("item[4] must be a number")
-            };
-            IpNet::new(IpAddr::from_str(item[3]).unwrap(), prefix_len).unwrap()
-        })
+impl IpSource for Registry {
+    fn name(&self) -> String {
+        Registry::name(self).to_string()
+    }
+
+    /// Download the file and verify it against its published MD5.
+    fn fetch(&self) -> Result<Vec<u8>> {
+        let data = super::download(self.url())?;
+        self.checksum().verify(self.url(), &data)?;
+        Ok(data)
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        countries: &[String],
+        filter: &RecordFilter,
+    ) -> Result<Vec<IpNet>> {
+        Ok(select(
+            &parse_records(&String::from_utf8_lossy(data))?,
+            countries,
+            filter,
+        ))
+    }
+
+    /// Built-in data used when fetching fails. Only APNIC data is built in.
+    fn builtin(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Apnic => {
+                let compressed_bytes = include_bytes!(concat!(env!("OUT_DIR"), "/apnic.zst"));
+                Some(zstd::stream::decode_all(Cursor::new(compressed_bytes)).unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    /// Revalidated with the server after 6 hours.
+    fn cache_expire(&self) -> Option<Duration> {
+        Some(super::REVALIDATE_EXPIRE)
+    }
+
+    /// Go through [`fetch_registry_data`], so the cache file is revalidated
+    /// and the download is checked like everywhere else.
+    fn get_ips(&self, countries: &[String], filter: &RecordFilter) -> Result<Vec<IpNet>> {
+        fetch_registry_data(*self, countries, filter)
+    }
+}
+
+/// Type of the resource of a [`Record`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    Asn,
+    Ipv4,
+    Ipv6,
+}
+
+impl RecordType {
+    /// Name of the type, as used in the type column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Asn => "asn",
+            Self::Ipv4 => "ipv4",
+            Self::Ipv6 => "ipv6",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Asn, Self::Ipv4, Self::Ipv6]
+            .into_iter()
+            .find(|x| x.name() == name)
+    }
+}
+
+/// Status of a [`Record`]. `available` and `reserved` only appear in the
+/// extended format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Allocated,
+    Assigned,
+    Available,
+    Reserved,
+}
+
+impl FromStr for Status {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "allocated" => Ok(Self::Allocated),
+            "assigned" => Ok(Self::Assigned),
+            "available" => Ok(Self::Available),
+            "reserved" => Ok(Self::Reserved),
+            _ => Err(Error::InvalidStatus(s.to_string())),
+        }
+    }
+}
+
+/// One record of a delegated-stats file, in the standard or extended format:
+/// `registry|cc|type|start|value|date|status[|opaque-id]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub registry: Registry,
+    /// ISO 3166 country code, empty for `available` and `reserved` records.
+    pub cc: String,
+    pub kind: RecordType,
+    /// First ASN or address of the record.
+    pub start: String,
+    /// Number of ASNs or IPv4 addresses, or the IPv6 prefix length.
+    pub value: u128,
+    /// Date of the delegation as `YYYYMMDD`, if known.
+    pub date: Option<u32>,
+    pub status: Status,
+    /// Opaque id of the holder, only in the extended format.
+    pub opaque_id: Option<String>,
+}
+
+impl fmt::Display for Record {
+    /// Format the record as a line of a delegated-stats file.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = self.kind.name();
+        let status = match self.status {
+            Status::Allocated => "allocated",
+            Status::Assigned => "assigned",
+            Status::Available => "available",
+            Status::Reserved => "reserved",
+        };
+        write!(
+            f,
+            "{}|{}|{kind}|{}|{}|{}|{status}",
+            self.registry.name(),
+            self.cc,
+            self.start,
+            self.value,
+            self.date.map(|d| d.to_string()).unwrap_or_default()
+        )?;
+        if let Some(opaque_id) = &self.opaque_id {
+            write!(f, "|{opaque_id}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Record {
+    /// Get the inclusive integer range of an IP record, or [`None`] for ASN
+    /// records and records with an invalid range.
+    fn range(&self) -> Option<(u128, u128)> {
+        let start = IpAddr::from_str(&self.start).ok()?;
+        let ipv6 = match self.kind {
+            RecordType::Asn => return None,
+            RecordType::Ipv4 => false,
+            RecordType::Ipv6 => true,
+        };
+        if start.is_ipv6() != ipv6 {
+            return None;
+        }
+        // ipv4 records hold the address count, ipv6 records hold the prefix length
+        let host_mask = if ipv6 {
+            u128::MAX
+                .checked_shr(u32::try_from(self.value).ok().filter(|&x| x <= 128)?)
+                .unwrap_or(0)
+        } else {
+            self.value.checked_sub(1)?
+        };
+        let start = cidr::to_u128(start);
+        let end = start.checked_add(host_mask)?;
+        if !ipv6 && end > u32::MAX as u128 {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// Split the record into the minimal list of CIDR blocks covering exactly
+    /// its range, as the count of an ipv4 record is not always a power of two.
+    ///
+    /// Returns an empty list for ASN records.
+    pub fn nets(&self) -> Vec<IpNet> {
+        self.range()
+            .map(|(start, end)| cidr::decompose(start, end, self.kind == RecordType::Ipv6))
+            .unwrap_or_default()
+    }
+}
+
+/// Parse a date given as `YYYYMMDD` or `YYYY-MM-DD`.
+pub fn parse_date(s: &str) -> Option<u32> {
+    let digits = s.replace('-', "");
+    if digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Format a `YYYYMMDD` date as `YYYY-MM-DD`.
+pub fn format_date(date: u32) -> String {
+    format!("{}-{:02}-{:02}", date / 10000, date / 100 % 100, date % 100)
+}
+
+/// Version line of a delegated-stats file,
+/// `version|registry|serial|records|startdate|enddate|UTCoffset`, with the
+/// summary lines following it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: String,
+    pub registry: Registry,
+    /// Serial number of the file, usually the date it was generated.
+    pub serial: String,
+    /// Number of records in the file, without the header and summary lines.
+    pub records: u64,
+    /// Earliest delegation date of the records as `YYYYMMDD`, if known.
+    pub start_date: Option<u32>,
+    /// Date the file was generated as `YYYYMMDD`, if known.
+    pub end_date: Option<u32>,
+    /// Offset from UTC of the dates, like `+1000`.
+    pub utc_offset: String,
+    /// Number of records of each type, from the summary lines.
+    pub summary: Vec<(RecordType, u64)>,
+}
+
+/// Filter on the status and delegation date of records.
+#[derive(Debug, Clone, Default)]
+pub struct RecordFilter {
+    /// Only records with one of these statuses, or any status if empty.
+    pub statuses: Vec<Status>,
+    /// Only records delegated on or after this date (`YYYYMMDD`).
+    pub since: Option<u32>,
+    /// Only records delegated before this date (`YYYYMMDD`).
+    pub before: Option<u32>,
+}
+
+impl RecordFilter {
+    /// Check whether a record passes the filter. Records without a date never
+    /// pass a date bound.
+    pub fn matches(&self, record: &Record) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&record.status))
+            && self
+                .since
+                .is_none_or(|since| record.date.is_some_and(|date| date >= since))
+            && self
+                .before
+                .is_none_or(|before| record.date.is_some_and(|date| date < before))
+    }
+}
+
+/// Get the CIDR blocks of the IP records of the given country codes that pass
+/// `filter`. Country codes are case-insensitive.
+pub fn select(
+    records: &[Record],
+    countries: &[impl AsRef<str>],
+    filter: &RecordFilter,
+) -> Vec<IpNet> {
+    select_records(records, countries, filter)
+        .flat_map(Record::nets)
         .collect()
 }
 
+/// Get the records of the given country codes that pass `filter`, including
+/// ASN records. Country codes are case-insensitive.
+pub fn select_records<'a>(
+    records: &'a [Record],
+    countries: &'a [impl AsRef<str>],
+    filter: &'a RecordFilter,
+) -> impl Iterator<Item = &'a Record> {
+    records
+        .iter()
+        .filter(|r| {
+            countries
+                .iter()
+                .any(|c| c.as_ref().eq_ignore_ascii_case(&r.cc))
+        })
+        .filter(|r| filter.matches(r))
+}
+
+/// Fetch IP data from apnic.net, add it to cache and return the parsed data of
+/// the given country codes.
+///
+/// The cached file is shared by all country selections.
+pub fn fetch_ip_data(countries: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    fetch_registry_data(Registry::Apnic, countries, &RecordFilter::default())
+}
+
+/// Fetch IP data from a registry, add it to cache and return the parsed data of
+/// the given country codes that pass `filter`.
+///
+/// Each registry has its own cache file, shared by all country selections.
+pub fn fetch_registry_data(
+    registry: Registry,
+    countries: &[impl AsRef<str>],
+    filter: &RecordFilter,
+) -> Result<Vec<IpNet>> {
+    Ok(select(
+        &fetch_registry_records(registry)?,
+        countries,
+        filter,
+    ))
+}
+
+/// Fetch the records of a registry, add the file to cache and return all
+/// records in it.
+pub fn fetch_registry_records(registry: Registry) -> Result<Vec<Record>> {
+    super::fetch_cached(
+        registry.name(),
+        registry.url(),
+        registry.checks(),
+        || registry.builtin(),
+        |data| parse_records(&String::from_utf8_lossy(data)),
+    )
+}
+
+/// Fetch the file of a registry, add it to cache and return its headers.
+pub fn fetch_registry_headers(registry: Registry) -> Result<Vec<Header>> {
+    super::fetch_cached(
+        registry.name(),
+        registry.url(),
+        registry.checks(),
+        || registry.builtin(),
+        |data| Ok(parse_headers(&String::from_utf8_lossy(data))),
+    )
+}
+
+/// Fetch IP data from all registries and return the parsed data of the given
+/// country codes that pass `filter`.
+pub fn fetch_all_registries_data(
+    countries: &[impl AsRef<str>],
+    filter: &RecordFilter,
+) -> Result<Vec<IpNet>> {
+    let mut ips = vec![];
+    for registry in Registry::ALL {
+        ips.extend(fetch_registry_data(registry, countries, filter)?);
+    }
+    Ok(ips)
+}
+
+/// Parse IP data of the given country codes from the delegated-stats file of
+/// any registry, failing on the first malformed line. Country codes are
+/// case-insensitive.
+pub fn parse_ip_data(content: &str, countries: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    Ok(select(
+        &parse_records(content)?,
+        countries,
+        &RecordFilter::default(),
+    ))
+}
+
+/// Parse IP data of the given country codes from str, skipping malformed lines.
+///
+/// Returns the parsed data and a [`Error::ParseError`] for every skipped line.
+pub fn parse_ip_data_lenient(
+    content: &str,
+    countries: &[impl AsRef<str>],
+) -> (Vec<IpNet>, Vec<Error>) {
+    let (records, errors) = parse_records_lenient(content);
+    (
+        select(&records, countries, &RecordFilter::default()),
+        errors,
+    )
+}
+
+/// Parse all records of a delegated-stats file in the standard or extended
+/// format, failing on the first malformed line.
+pub fn parse_records(content: &str) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    for (index, line) in content.lines().enumerate() {
+        records.extend(parse_line(index + 1, line)?);
+    }
+    Ok(records)
+}
+
+/// Parse all records of a delegated-stats file, skipping malformed lines.
+///
+/// Returns the records and a [`Error::ParseError`] for every skipped line.
+pub fn parse_records_lenient(content: &str) -> (Vec<Record>, Vec<Error>) {
+    let mut records = vec![];
+    let mut errors = vec![];
+    for (index, line) in content.lines().enumerate() {
+        match parse_line(index + 1, line) {
+            Ok(record) => records.extend(record),
+            Err(e) => {
+                warn!("Skipping malformed line: {e}");
+                errors.push(e);
+            }
+        }
+    }
+    (records, errors)
+}
+
+/// Parse the headers of a delegated-stats file, or of several concatenated
+/// files. Malformed header and summary lines are skipped.
+pub fn parse_headers(content: &str) -> Vec<Header> {
+    let mut headers: Vec<Header> = vec![];
+    for line in content.lines().filter(|line| !line.starts_with('#')) {
+        let item = line.split('|').collect::<Vec<&str>>();
+        if let [version, registry, serial, records, start_date, end_date, utc_offset] = item[..] {
+            let (Some(registry), Ok(records)) = (Registry::from_name(registry), records.parse())
+            else {
+                continue;
+            };
+            if version.starts_with(|c: char| c.is_ascii_digit()) {
+                let date = |date| parse_date(date).filter(|&date| date != 0);
+                headers.push(Header {
+                    version: version.to_string(),
+                    registry,
+                    serial: serial.to_string(),
+                    records,
+                    start_date: date(start_date),
+                    end_date: date(end_date),
+                    utc_offset: utc_offset.to_string(),
+                    summary: vec![],
+                });
+            }
+        } else if let [registry, "*", kind, "*", count, "summary"] = item[..] {
+            let (Some(header), Some(kind), Ok(count)) = (
+                headers
+                    .last_mut()
+                    .filter(|h| Registry::from_name(registry) == Some(h.registry)),
+                RecordType::from_name(kind),
+                count.parse(),
+            ) else {
+                continue;
+            };
+            header.summary.push((kind, count));
+        }
+    }
+    headers
+}
+
+/// Check that the record counts in the headers match the records, which they
+/// do not in a truncated file. Every file must have a header.
+pub fn check_counts(headers: &[Header], records: &[Record]) -> Result<()> {
+    if headers.is_empty() {
+        return Err(Error::SanityCheckFailed("no header found".to_string()));
+    }
+    for header in headers {
+        let registry = header.registry;
+        let count = |kind: Option<RecordType>| {
+            records
+                .iter()
+                .filter(|r| r.registry == registry && kind.is_none_or(|kind| r.kind == kind))
+                .count() as u64
+        };
+        let expected = header
+            .summary
+            .iter()
+            .map(|&(kind, count)| (Some(kind), count));
+        for (kind, expected) in std::iter::once((None, header.records)).chain(expected) {
+            let found = count(kind);
+            if found != expected {
+                return Err(Error::SanityCheckFailed(format!(
+                    "{} header declares {expected} {} records, found {found}",
+                    registry.name(),
+                    kind.map(|kind| kind.name()).unwrap_or("total")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse all records of a delegated-stats file, and check their counts
+/// against the headers.
+fn parse_checked_records(data: &[u8]) -> Result<Vec<Record>> {
+    let content = String::from_utf8_lossy(data);
+    let records = parse_records(&content)?;
+    check_counts(&parse_headers(&content), &records)?;
+    Ok(records)
+}
+
+/// Parse one line of the delegated file, returning [`None`] for comments,
+/// version and summary lines.
+fn parse_line(line_number: usize, line: &str) -> Result<Option<Record>> {
+    let err = || Error::ParseError {
+        line: line_number,
+        text: line.to_string(),
+    };
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    // version line has 7 fields, summary lines 6 and records at least 7.
+    let item = line.split('|').collect::<Vec<&str>>();
+    if item.len() < 6 {
+        return Err(err());
+    }
+    let Some(registry) = Registry::from_name(item[0]) else {
+        return Ok(None);
+    };
+    if item[1] == "*" && item[5] == "summary" {
+        return Ok(None);
+    }
+    if item.len() < 7 {
+        return Err(err());
+    }
+    let kind = RecordType::from_name(item[2]).ok_or_else(err)?;
+    let date = match item[5] {
+        "" | "00000000" => None,
+        date => Some(parse_date(date).ok_or_else(err)?),
+    };
+    let record = Record {
+        registry,
+        cc: item[1].to_string(),
+        kind,
+        start: item[3].to_string(),
+        value: item[4].parse().map_err(|_| err())?,
+        date,
+        status: item[6].parse().map_err(|_| err())?,
+        opaque_id: item.get(7).filter(|x| !x.is_empty()).map(|x| x.to_string()),
+    };
+    if kind != RecordType::Asn && record.range().is_none() {
+        return Err(err());
+    }
+    Ok(Some(record))
+}
+
 #[cfg(test)]
 mod tests {
     use log::LevelFilter;
 
     use super::*;
+    use crate::source::Source;
 
     #[test]
     fn test_fetch_ip_data() {
@@ -71,7 +606,11 @@ mod tests {
             .filter_module("reqwest", LevelFilter::Info)
             .parse_default_env()
             .try_init();
-        assert!(fetch_ip_data().is_ok());
+        crate::cache::use_test_cache_dir();
+        assert!(fetch_ip_data(&["CN"]).is_ok());
+        crate::cache::Cache::new(Registry::Apnic.name(), std::time::Duration::ZERO)
+            .remove()
+            .unwrap();
     }
 
     #[test]
@@ -80,7 +619,202 @@ mod tests {
             std::fs::read_to_string("tests_assets/apnic.txt")
                 .unwrap()
                 .as_str(),
-        );
+            &["CN"],
+        )
+        .unwrap();
         assert_eq!(results[0], IpNet::from_str("1.0.1.0/24").unwrap());
     }
+
+    #[test]
+    fn test_parse_non_power_of_two() {
+        let results = parse_ip_data(
+            "apnic|CN|ipv4|1.0.0.0|768|20110414|allocated
+apnic|CN|ipv4|1.0.4.0|1536|20110414|allocated
+apnic|CN|ipv6|2001:250::|31|20000426|allocated",
+            &["CN"],
+        )
+        .unwrap();
+        let expected = [
+            "1.0.0.0/23",
+            "1.0.2.0/24",
+            "1.0.4.0/22",
+            "1.0.8.0/23",
+            "2001:250::/31",
+        ]
+        .map(|x| IpNet::from_str(x).unwrap());
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let content = "2|apnic|20241215|80468|19830613|20241214|+1000
+apnic|*|ipv4|*|52100|summary
+apnic|CN|ipv4|1.0.1.0|256|20110414|allocated
+apnic|CN|ipv4|1.0.2.0|0|20110414|allocated
+apnic|CN|ipv4|1.0.
+apnic|CN|ipv6|2001:250::|31|20000426|allocated";
+        let err = parse_ip_data(content, &["CN"]).unwrap_err();
+        assert!(matches!(err, Error::ParseError { line: 4, .. }));
+
+        let (results, errors) = parse_ip_data_lenient(content, &["CN"]);
+        assert_eq!(
+            results,
+            ["1.0.1.0/24", "2001:250::/31"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                Error::ParseError { line: 4, .. },
+                Error::ParseError { line: 5, text }
+            ] if text == "apnic|CN|ipv4|1.0."
+        ));
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let content = "# comment
+2|apnic|20241215|80468|19830613|20241214|+1000
+apnic|*|asn|*|12703|summary
+apnic|*|ipv4|*|52100|summary
+apnic|*|ipv6|*|15665|summary
+apnic|CN|ipv4|1.0.1.0|256|20110414|allocated
+2.3|arin|20241215|3|00000000|20241214|-0500
+arin|*|ipv4|*|3|summary
+arin|*|ipv6|*|x|summary
+apnic|*|ipv6|*|1|summary";
+        let headers = parse_headers(content);
+        assert_eq!(
+            headers[0],
+            Header {
+                version: "2".to_string(),
+                registry: Registry::Apnic,
+                serial: "20241215".to_string(),
+                records: 80468,
+                start_date: Some(19830613),
+                end_date: Some(20241214),
+                utc_offset: "+1000".to_string(),
+                summary: vec![
+                    (RecordType::Asn, 12703),
+                    (RecordType::Ipv4, 52100),
+                    (RecordType::Ipv6, 15665)
+                ],
+            }
+        );
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[1].version, "2.3");
+        assert_eq!(headers[1].start_date, None);
+        assert_eq!(headers[1].summary, [(RecordType::Ipv4, 3)]);
+    }
+
+    #[test]
+    fn test_check_counts() {
+        let content = "2|apnic|20241215|3|19830613|20241214|+1000
+apnic|*|asn|*|1|summary
+apnic|*|ipv4|*|2|summary
+apnic|JP|asn|173|1|20020801|allocated
+apnic|CN|ipv4|1.0.1.0|256|20110414|allocated
+apnic|CN|ipv4|1.0.2.0|512|20110414|allocated";
+        let check = |content: &str| check_counts(&parse_headers(content), &parse_records(content)?);
+        assert!(check(content).is_ok());
+        let truncated = &content[..content.rfind('\n').unwrap()];
+        assert!(matches!(
+            check(truncated),
+            Err(Error::SanityCheckFailed(e)) if e == "apnic header declares 3 total records, found 2"
+        ));
+        let content = content.replace("apnic|JP|asn|173|1|", "apnic|JP|ipv4|1.0.16.0|4096|");
+        assert!(matches!(
+            check(&content),
+            Err(Error::SanityCheckFailed(e)) if e == "apnic header declares 1 asn records, found 0"
+        ));
+        assert!(check("<html><body>Login</body></html>").is_err());
+    }
+
+    #[test]
+    fn test_parse_countries() {
+        let content = std::fs::read_to_string("tests_assets/apnic.txt").unwrap();
+        let results = parse_ip_data(&content, &["jp", "TH"]).unwrap();
+        assert_eq!(
+            results,
+            ["1.0.16.0/20", "1.0.64.0/18", "1.0.128.0/17"].map(|x| IpNet::from_str(x).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_other_registries() {
+        let content = "2|ripencc|1734303599|226016|19830705|20241215|+0100
+ripencc|*|ipv4|*|92178|summary
+ripencc|CN|ipv4|2.58.32.0|1024|20190627|allocated
+arin|CN|ipv4|23.236.128.0|768|20140212|allocated|c2b7f4a7d3b4e1b2c3e5e3b8d3f5c0f7
+afrinic|ZA|ipv4|41.0.0.0|2097152|20070419|allocated";
+        assert_eq!(
+            parse_ip_data(content, &["CN"]).unwrap(),
+            ["2.58.32.0/22", "23.236.128.0/23", "23.236.130.0/24"]
+                .map(|x| IpNet::from_str(x).unwrap())
+        );
+        for source in [Source::arin, Source::rir] {
+            assert_eq!(
+                source
+                    .parse(
+                        content.as_bytes(),
+                        &["CN".to_string()],
+                        &RecordFilter::default()
+                    )
+                    .unwrap(),
+                parse_ip_data(content, &["CN"]).unwrap()
+            );
+        }
+        assert_eq!(Registry::Arin.cache_expire(), Source::arin.cache_expire());
+    }
+
+    #[test]
+    fn test_parse_extended() {
+        let content = "2.3|apnic|20241215|80468|19830613|20241214|+1000
+apnic|*|ipv4|*|52100|summary
+apnic|CN|ipv4|1.0.1.0|256|20110414|allocated|A92E1062
+apnic|CN|ipv4|1.0.2.0|512|20240101|assigned|A92E1062
+apnic||ipv4|1.0.4.0|1024||available
+apnic|ZZ|ipv6|2001:db8::|32|00000000|reserved|";
+        let records = parse_records(content).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[0],
+            Record {
+                registry: Registry::Apnic,
+                cc: "CN".to_string(),
+                kind: RecordType::Ipv4,
+                start: "1.0.1.0".to_string(),
+                value: 256,
+                date: Some(20110414),
+                status: Status::Allocated,
+                opaque_id: Some("A92E1062".to_string()),
+            }
+        );
+        assert_eq!(records[2].status, Status::Available);
+        assert_eq!(records[3].date, None);
+        assert_eq!(records[3].opaque_id, None);
+        assert_eq!(
+            records[0].to_string(),
+            "apnic|CN|ipv4|1.0.1.0|256|20110414|allocated|A92E1062"
+        );
+        assert_eq!(
+            records[2].to_string(),
+            "apnic||ipv4|1.0.4.0|1024||available"
+        );
+
+        let filter = RecordFilter {
+            statuses: vec![Status::Allocated],
+            ..Default::default()
+        };
+        assert_eq!(select(&records, &["CN"], &filter), records[0].nets());
+        let filter = RecordFilter {
+            before: parse_date("2024-01-01"),
+            ..Default::default()
+        };
+        assert_eq!(select(&records, &["CN"], &filter), records[0].nets());
+        let filter = RecordFilter {
+            since: parse_date("20240101"),
+            ..Default::default()
+        };
+        assert_eq!(select(&records, &["CN"], &filter), records[1].nets());
+    }
 }
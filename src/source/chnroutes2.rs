@@ -0,0 +1,80 @@
+use ipnet::IpNet;
+use log::warn;
+
+use super::Checks;
+use crate::{Error, Result};
+
+pub(crate) const URL: &str =
+    "https://raw.githubusercontent.com/misakaio/chnroutes2/master/chnroutes.txt";
+
+/// Fetch the chnroutes2 list, add it to cache and return the parsed data.
+///
+/// There is no built-in chnroutes2 data, so this fails if fetching fails and
+/// nothing is cached.
+///
+/// chnroutes2 only holds CN prefixes, so nothing is returned if `CN` is not
+/// among `countries`.
+pub fn fetch_ip_data(countries: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    if !provides(countries) {
+        return Ok(vec![]);
+    }
+    let parse = |data: &[u8]| parse_ip_data(&String::from_utf8_lossy(data));
+    let checks = Checks {
+        guarded_ips: Some(parse),
+        ..Default::default()
+    };
+    super::fetch_cached("chnroutes2", URL, checks, || None, parse)
+}
+
+/// Whether `CN` is among `countries`, warning if it is not.
+pub(crate) fn provides(countries: &[impl AsRef<str>]) -> bool {
+    let provides = countries
+        .iter()
+        .any(|c| c.as_ref().eq_ignore_ascii_case("CN"));
+    if !provides {
+        warn!("chnroutes2 only provides CN data, no IP selected.");
+    }
+    provides
+}
+
+/// Parse a CIDR list with one prefix per line, where lines starting with `#`
+/// are comments.
+pub fn parse_ip_data(content: &str) -> Result<Vec<IpNet>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            line.parse::<IpNet>().map_err(|_| Error::ParseError {
+                line: index + 1,
+                text: line.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_data() {
+        let content = "# comment
+1.0.1.0/24
+1.0.2.0/23
+
+2001:250::/31
+";
+        assert_eq!(
+            parse_ip_data(content).unwrap(),
+            ["1.0.1.0/24", "1.0.2.0/23", "2001:250::/31"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert!(matches!(
+            parse_ip_data("1.0.1.0/24\n<html>").unwrap_err(),
+            Error::ParseError { line: 2, .. }
+        ));
+    }
+}
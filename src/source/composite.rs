@@ -0,0 +1,248 @@
+//! Sources combined with set operations, such as "APNIC CN ∪ chnroutes2 − a
+//! block list".
+
+use std::{iter::Peekable, str::FromStr};
+
+use ipnet::IpNet;
+
+use super::{
+    apnic::{RecordFilter, Registry},
+    IpSource, Source,
+};
+use crate::{cidr, Error, Result};
+
+/// A set expression over [`Source`]s, whose IPs are aggregated after every
+/// operation.
+///
+/// Written with `+` for union, `&` for intersection and `-` for difference,
+/// separated from the sources by spaces. Operators are evaluated from left to
+/// right, use parentheses to group them, for example
+/// `(apnic + chnroutes2) - file:///etc/blocklist.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Composite {
+    Source(Source),
+    Union(Box<Composite>, Box<Composite>),
+    Intersection(Box<Composite>, Box<Composite>),
+    Difference(Box<Composite>, Box<Composite>),
+}
+
+impl Default for Composite {
+    fn default() -> Self {
+        Self::Source(Source::default())
+    }
+}
+
+impl From<Source> for Composite {
+    fn from(source: Source) -> Self {
+        Self::Source(source)
+    }
+}
+
+impl Composite {
+    /// Addresses in `self` or `other`.
+    pub fn union(self, other: impl Into<Self>) -> Self {
+        Self::Union(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Addresses in both `self` and `other`.
+    pub fn intersection(self, other: impl Into<Self>) -> Self {
+        Self::Intersection(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Addresses in `self` but not in `other`.
+    pub fn difference(self, other: impl Into<Self>) -> Self {
+        Self::Difference(Box::new(self), Box::new(other.into()))
+    }
+
+    /// Get the registries whose delegated-stats files any of the sources reads.
+    pub fn registries(&self) -> Vec<Registry> {
+        let mut registries = match self {
+            Self::Source(source) => source.registries(),
+            Self::Union(a, b) | Self::Intersection(a, b) | Self::Difference(a, b) => {
+                [a.registries(), b.registries()].concat()
+            }
+        };
+        registries.sort_by_key(|r| Registry::ALL.iter().position(|x| x == r));
+        registries.dedup();
+        registries
+    }
+}
+
+impl IpSource for Composite {
+    fn name(&self) -> String {
+        match self {
+            Self::Source(source) => source.name(),
+            Self::Union(a, b) => format!("({} + {})", a.name(), b.name()),
+            Self::Intersection(a, b) => format!("({} & {})", a.name(), b.name()),
+            Self::Difference(a, b) => format!("({} - {})", a.name(), b.name()),
+        }
+    }
+
+    /// A composite has no raw data of its own, its sources are fetched by
+    /// [`get_ips`](IpSource::get_ips) instead.
+    fn fetch(&self) -> Result<Vec<u8>> {
+        Err(Error::Unsupported("fetching a composite source"))
+    }
+
+    fn parse(&self, _: &[u8], _: &[String], _: &RecordFilter) -> Result<Vec<IpNet>> {
+        Err(Error::Unsupported("parsing a composite source"))
+    }
+
+    fn get_ips(&self, countries: &[String], filter: &RecordFilter) -> Result<Vec<IpNet>> {
+        let operands = |a: &Self, b: &Self| -> Result<_> {
+            Ok((a.get_ips(countries, filter)?, b.get_ips(countries, filter)?))
+        };
+        Ok(match self {
+            Self::Source(source) => cidr::aggregate(&IpSource::get_ips(source, countries, filter)?),
+            Self::Union(a, b) => {
+                let (mut a, b) = operands(a, b)?;
+                a.extend(b);
+                cidr::aggregate(&a)
+            }
+            Self::Intersection(a, b) => {
+                let (a, b) = operands(a, b)?;
+                cidr::intersection(&a, &b)
+            }
+            Self::Difference(a, b) => {
+                let (a, b) = operands(a, b)?;
+                cidr::difference(&a, &b)
+            }
+        })
+    }
+}
+
+impl FromStr for Composite {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = tokenize(s).into_iter().peekable();
+        let composite = parse_expression(&mut tokens)?;
+        match tokens.next() {
+            Some(token) => Err(invalid(format!("unexpected {token:?}"))),
+            None => Ok(composite),
+        }
+    }
+}
+
+/// Split an expression into sources, operators and parentheses. Parentheses
+/// may be attached to the sources.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    for mut word in s.split_whitespace() {
+        while let Some(rest) = word.strip_prefix('(') {
+            tokens.push("(");
+            word = rest;
+        }
+        let closing = word.len() - word.trim_end_matches(')').len();
+        word = &word[..word.len() - closing];
+        if !word.is_empty() {
+            tokens.push(word);
+        }
+        tokens.extend(std::iter::repeat_n(")", closing));
+    }
+    tokens
+}
+
+fn parse_expression<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> Result<Composite> {
+    let mut composite = parse_operand(tokens)?;
+    while let Some(&operator) = tokens.peek() {
+        let combine: fn(Composite, Composite) -> Composite = match operator {
+            "+" => Composite::union,
+            "&" => Composite::intersection,
+            "-" => Composite::difference,
+            _ => break,
+        };
+        tokens.next();
+        composite = combine(composite, parse_operand(tokens)?);
+    }
+    Ok(composite)
+}
+
+fn parse_operand<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> Result<Composite> {
+    match tokens.next() {
+        Some("(") => {
+            let composite = parse_expression(tokens)?;
+            match tokens.next() {
+                Some(")") => Ok(composite),
+                _ => Err(invalid("missing )")),
+            }
+        }
+        Some(token @ (")" | "+" | "&" | "-")) => Err(invalid(format!("unexpected {token:?}"))),
+        Some(token) => Ok(Composite::Source(token.parse()?)),
+        None => Err(invalid("missing source")),
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidExpression(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Composite::from_str("rir").unwrap(), Source::rir.into());
+        assert_eq!(
+            Composite::from_str("(apnic + chnroutes2) - file:///tmp/block.txt").unwrap(),
+            Composite::from(Source::apnic)
+                .union(Source::chnroutes2)
+                .difference(Source::list("file:///tmp/block.txt".to_string()))
+        );
+        assert_eq!(
+            Composite::from_str("apnic & (arin - ripencc)").unwrap(),
+            Composite::from(Source::apnic)
+                .intersection(Composite::from(Source::arin).difference(Source::ripencc))
+        );
+        for s in [
+            "",
+            "apnic +",
+            "(apnic",
+            "apnic)",
+            "apnic chnroutes2",
+            "- apnic",
+        ] {
+            assert!(matches!(
+                Composite::from_str(s),
+                Err(Error::InvalidExpression(_))
+            ));
+        }
+        assert!(matches!(
+            Composite::from_str("apnic + nothing"),
+            Err(Error::InvalidSource)
+        ));
+        assert_eq!(
+            Composite::from_str("(arin + chnroutes2) - rir")
+                .unwrap()
+                .registries(),
+            Registry::ALL
+        );
+    }
+
+    #[test]
+    fn test_get_ips() {
+        let path = std::env::temp_dir().join("chnroutes-composite-test.txt");
+        std::fs::write(&path, "1.0.1.0/24\n1.0.8.0/21\n").unwrap();
+        let list = Source::list(format!("file://{}", path.display()));
+        let countries = ["CN".to_string()];
+        let get_ips = |composite: Composite| {
+            composite
+                .get_ips(&countries, &RecordFilter::default())
+                .unwrap()
+        };
+        let test = || Composite::from(Source::test);
+        assert_eq!(
+            get_ips(test().union(list.clone())),
+            ["1.0.1.0/24", "1.0.2.0/23", "1.0.8.0/21"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert_eq!(
+            get_ips(test().intersection(list.clone())),
+            [IpNet::from_str("1.0.1.0/24").unwrap()]
+        );
+        assert_eq!(
+            get_ips(test().difference(list)),
+            [IpNet::from_str("1.0.2.0/23").unwrap()]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
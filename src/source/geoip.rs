@@ -0,0 +1,236 @@
+//! V2Ray `geoip.dat` files, as published by
+//! <https://github.com/Loyalsoldier/v2ray-rules-dat>. The file is a protobuf
+//! `GeoIPList`:
+//!
+//! ```protobuf
+//! message CIDR { bytes ip = 1; uint32 prefix = 2; }
+//! message GeoIP { string country_code = 1; repeated CIDR cidr = 2; bool reverse_match = 3; }
+//! message GeoIPList { repeated GeoIP entry = 1; }
+//! ```
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+use super::{Checks, Checksum};
+use crate::{cidr, Error, Result};
+
+pub const DEFAULT_URL: &str =
+    "https://github.com/Loyalsoldier/v2ray-rules-dat/releases/latest/download/geoip.dat";
+
+/// Fetch a `geoip.dat` from a URL, or read it from a local path, and return
+/// the CIDRs of the given tags, such as `cn`, `private` or `telegram`. Tags are
+/// case-insensitive.
+///
+/// Files fetched from a URL are cached.
+pub fn fetch_ip_data(location: &str, tags: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    if location == DEFAULT_URL {
+        let checks = Checks {
+            checksum: checksum(location),
+            guarded_ips: Some(|data| parse_ip_data(data, &["cn"])),
+        };
+        return super::fetch_cached(
+            "geoip",
+            location,
+            checks,
+            || None,
+            |data| parse_ip_data(data, tags),
+        );
+    }
+    super::fetch_location("geoip", location, |data| parse_ip_data(data, tags))
+}
+
+/// Checksum of a `geoip.dat` at `location`, only known for the default one.
+pub(crate) fn checksum(location: &str) -> Option<Checksum> {
+    (location == DEFAULT_URL).then(|| Checksum::Sha256(format!("{DEFAULT_URL}.sha256sum")))
+}
+
+/// Parse the CIDRs of the given tags from a `geoip.dat` file. Tags are
+/// case-insensitive.
+pub fn parse_ip_data(data: &[u8], tags: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    let mut ips = vec![];
+    for field in Fields(data) {
+        let (1, Value::Bytes(entry)) = field? else {
+            continue;
+        };
+        let (mut code, mut cidrs, mut reverse_match) = ("", vec![], false);
+        for field in Fields(entry) {
+            match field? {
+                (1, Value::Bytes(x)) => {
+                    code = std::str::from_utf8(x)
+                        .map_err(|_| invalid("country code is not valid UTF-8"))?
+                }
+                (2, Value::Bytes(x)) => cidrs.push(x),
+                (3, Value::Varint(x)) => reverse_match = x != 0,
+                _ => {}
+            }
+        }
+        if !tags.iter().any(|t| t.as_ref().eq_ignore_ascii_case(code)) {
+            continue;
+        }
+        let nets = cidrs
+            .into_iter()
+            .map(parse_cidr)
+            .collect::<Result<Vec<_>>>()?;
+        if reverse_match {
+            ips.extend(cidr::difference(
+                &["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+                &nets,
+            ));
+        } else {
+            ips.extend(nets);
+        }
+    }
+    Ok(ips)
+}
+
+/// Parse a `CIDR` message.
+fn parse_cidr(data: &[u8]) -> Result<IpNet> {
+    let (mut ip, mut prefix) = (None, 0);
+    for field in Fields(data) {
+        match field? {
+            (1, Value::Bytes(x)) => ip = Some(x),
+            (2, Value::Varint(x)) => prefix = x,
+            _ => {}
+        }
+    }
+    let ip = match ip {
+        Some(x) if x.len() == 4 => IpAddr::from(<[u8; 4]>::try_from(x).unwrap()),
+        Some(x) if x.len() == 16 => IpAddr::from(<[u8; 16]>::try_from(x).unwrap()),
+        _ => return Err(invalid("CIDR without a valid ip")),
+    };
+    u8::try_from(prefix)
+        .ok()
+        .and_then(|prefix| IpNet::new(ip, prefix).ok())
+        .map(|net| net.trunc())
+        .ok_or_else(|| invalid(format!("invalid prefix length {prefix} for {ip}")))
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::DecodeError(reason.into())
+}
+
+/// A protobuf field value. 64-bit and 32-bit fixed values are returned as
+/// bytes.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Iterator over the `(field number, value)` pairs of a protobuf message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .0
+                .split_first()
+                .ok_or_else(|| invalid("truncated varint"))?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8]> {
+        match usize::try_from(len) {
+            Ok(len) if len <= self.0.len() => {
+                let (head, rest) = self.0.split_at(len);
+                self.0 = rest;
+                Ok(head)
+            }
+            _ => Err(invalid("truncated field")),
+        }
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => Value::Bytes(self.take(8)?),
+            2 => {
+                let len = self.varint()?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Bytes(self.take(4)?),
+            wire_type => return Err(invalid(format!("unsupported wire type {wire_type}"))),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn varint(mut value: u64, buf: &mut Vec<u8>) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], buf: &mut Vec<u8>) {
+        varint(field << 3 | 2, buf);
+        varint(bytes.len() as u64, buf);
+        buf.extend_from_slice(bytes);
+    }
+
+    fn geoip(code: &str, nets: &[&str]) -> Vec<u8> {
+        let mut entry = vec![];
+        bytes_field(1, code.as_bytes(), &mut entry);
+        for net in nets {
+            let net = IpNet::from_str(net).unwrap();
+            let mut cidr = vec![];
+            match net.addr() {
+                IpAddr::V4(ip) => bytes_field(1, &ip.octets(), &mut cidr),
+                IpAddr::V6(ip) => bytes_field(1, &ip.octets(), &mut cidr),
+            }
+            varint(2 << 3, &mut cidr);
+            varint(net.prefix_len() as u64, &mut cidr);
+            bytes_field(2, &cidr, &mut entry);
+        }
+        let mut list = vec![];
+        bytes_field(1, &entry, &mut list);
+        list
+    }
+
+    #[test]
+    fn test_parse_ip_data() {
+        let mut data = geoip("CN", &["1.0.1.0/24", "2001:250::/31"]);
+        data.extend(geoip("PRIVATE", &["10.0.0.0/8"]));
+        data.extend(geoip("TELEGRAM", &["91.108.4.0/22"]));
+        assert_eq!(
+            parse_ip_data(&data, &["cn", "telegram"]).unwrap(),
+            ["1.0.1.0/24", "2001:250::/31", "91.108.4.0/22"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert!(parse_ip_data(&data, &["jp"]).unwrap().is_empty());
+        assert!(matches!(
+            parse_ip_data(&data[..data.len() - 3], &["cn"]),
+            Err(Error::DecodeError(_))
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+//! Plain CIDR lists, such as a team's own curated routes.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+use crate::{Error, Result};
+
+/// Fetch a CIDR list from an `http(s)://` URL, or read it from a `file://`
+/// location, and return the parsed data. Lists fetched from a URL are cached.
+pub fn fetch_ip_data(location: &str) -> Result<Vec<IpNet>> {
+    super::fetch_location("list", location, |data| {
+        parse_ip_data(&String::from_utf8_lossy(data))
+    })
+}
+
+/// Parse a CIDR list with one prefix per line. Everything after a `#` is a
+/// comment, and a bare address is taken as a `/32` or `/128` prefix.
+pub fn parse_ip_data(content: &str) -> Result<Vec<IpNet>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            line.parse::<IpNet>()
+                .or_else(|_| line.parse::<IpAddr>().map(IpNet::from))
+                .map(|net| net.trunc())
+                .map_err(|_| Error::ParseError {
+                    line: index + 1,
+                    text: line.to_string(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_data() {
+        let content = "# office
+10.1.0.0/16  # vpn
+192.168.1.1
+
+2001:db8::1
+2001:db8:1::/48
+";
+        assert_eq!(
+            parse_ip_data(content).unwrap(),
+            [
+                "10.1.0.0/16",
+                "192.168.1.1/32",
+                "2001:db8::1/128",
+                "2001:db8:1::/48"
+            ]
+            .map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert!(matches!(
+            parse_ip_data("10.1.0.0/16\nexample.com").unwrap_err(),
+            Error::ParseError { line: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_fetch_ip_data() {
+        let path = std::env::temp_dir().join("chnroutes-list-test.txt");
+        std::fs::write(&path, "1.0.1.0/24\n1.0.2.1\n").unwrap();
+        assert_eq!(
+            fetch_ip_data(&format!("file://{}", path.display())).unwrap(),
+            ["1.0.1.0/24", "1.0.2.1/32"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+//! MaxMind GeoLite2-Country / GeoIP2-Country and DB-IP country `.mmdb` files,
+//! read from a local path via <https://github.com/oschwald/maxminddb-rust>.
+
+use std::path::Path;
+
+use ipnet::IpNet;
+use ipnetwork::IpNetwork;
+use maxminddb::{geoip2, Reader};
+
+use crate::Result;
+
+/// Read a `.mmdb` file and return every network whose `country.iso_code` is
+/// one of `countries`. Country codes are case-insensitive.
+pub fn fetch_ip_data(path: impl AsRef<Path>, countries: &[impl AsRef<str>]) -> Result<Vec<IpNet>> {
+    parse_ip_data(&Reader::open_readfile(path)?, countries)
+}
+
+/// Walk a `.mmdb` database and return every network whose `country.iso_code`
+/// is one of `countries`.
+pub fn parse_ip_data<S: AsRef<[u8]>>(
+    reader: &Reader<S>,
+    countries: &[impl AsRef<str>],
+) -> Result<Vec<IpNet>> {
+    // The reader yields the IPv4 part of IPv6 databases (`::/96`) as IPv4
+    // networks and skips its aliases such as `::ffff:0:0/96`.
+    let all: IpNetwork = match reader.metadata.ip_version {
+        6 => "::/0".parse().unwrap(),
+        _ => "0.0.0.0/0".parse().unwrap(),
+    };
+    let mut ips = vec![];
+    for item in reader.within::<geoip2::Country>(all)? {
+        let item = item?;
+        let Some(code) = item.info.country.and_then(|c| c.iso_code) else {
+            continue;
+        };
+        if countries
+            .iter()
+            .any(|c| c.as_ref().eq_ignore_ascii_case(code))
+        {
+            ips.push(IpNet::new(item.ip_net.ip(), item.ip_net.prefix()).unwrap());
+        }
+    }
+    Ok(ips)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_fetch_ip_data() {
+        let path = "tests_assets/GeoLite2-Country-Test.mmdb";
+        assert_eq!(
+            fetch_ip_data(path, &["cn"]).unwrap(),
+            ["1.0.1.0/24", "1.0.2.0/23", "2001:250::/31"].map(|x| IpNet::from_str(x).unwrap())
+        );
+        assert_eq!(
+            fetch_ip_data(path, &["US", "JP"]).unwrap(),
+            ["8.8.8.0/24", "2001:4860::/32"].map(|x| IpNet::from_str(x).unwrap())
+        );
+    }
+}
@@ -1,31 +1,320 @@
-#[cfg(test)]
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use ipnet::IpNet;
+use log::{info, warn};
+use md5::Md5;
+use reqwest::{
+    header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+use self::apnic::{RecordFilter, Registry};
+use crate::{
+    cache::{Cache, Validators},
+    cidr::{self, Summary},
+    guard, Error, Result,
+};
 
 pub mod apnic;
+pub mod chnroutes2;
+pub mod composite;
+pub mod geoip;
+pub mod list;
+pub mod mmdb;
+
+/// How long fetched data is cached by default.
+const CACHE_EXPIRE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long data downloaded from a URL is cached before asking the server
+/// whether it has changed. Unchanged data is not downloaded again.
+const REVALIDATE_EXPIRE: Duration = Duration::from_secs(6 * 60 * 60);
 
 /// Choose a source to generate ip map.
-#[derive(Debug, Clone, Copy, Default, enum_tools::EnumTools)]
-#[enum_tools(as_str, from_str, iter, next)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
-#[repr(u8)]
 pub enum Source {
     #[default]
     apnic,
+    arin,
+    ripencc,
+    lacnic,
+    afrinic,
+    /// All five RIRs combined.
+    rir,
+    /// BGP-derived CN list from <https://github.com/misakaio/chnroutes2>.
+    chnroutes2,
+    /// V2Ray `geoip.dat` from a URL or local path, where countries select the
+    /// tags. Written as `geoip[:<location>]`, Loyalsoldier's release by
+    /// default.
+    geoip(String),
+    /// Local MaxMind or DB-IP country `.mmdb` file. Written as `mmdb:<path>`.
+    mmdb(String),
+    /// Plain CIDR list with one prefix or address per line, from a
+    /// `file://<path>` or `http(s)://` location. Countries do not apply.
+    list(String),
     #[cfg(test)]
     test,
 }
 
+impl FromStr for Source {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "apnic" => Ok(Self::apnic),
+            "arin" => Ok(Self::arin),
+            "ripencc" => Ok(Self::ripencc),
+            "lacnic" => Ok(Self::lacnic),
+            "afrinic" => Ok(Self::afrinic),
+            "rir" => Ok(Self::rir),
+            "chnroutes2" => Ok(Self::chnroutes2),
+            "geoip" => Ok(Self::geoip(geoip::DEFAULT_URL.to_string())),
+            _ => {
+                if let Some(location) = s.strip_prefix("geoip:") {
+                    Ok(Self::geoip(location.to_string()))
+                } else if let Some(path) = s.strip_prefix("mmdb:") {
+                    Ok(Self::mmdb(path.to_string()))
+                } else if ["file://", "http://", "https://"]
+                    .iter()
+                    .any(|scheme| s.starts_with(scheme))
+                {
+                    Ok(Self::list(s.to_string()))
+                } else {
+                    Err(Error::InvalidSource)
+                }
+            }
+        }
+    }
+}
+
 impl Source {
-    pub fn get_cn_ips(&self) -> crate::error::Result<Vec<IpNet>> {
+    pub fn get_cn_ips(&self) -> Result<Vec<IpNet>> {
         get_cn_ips(self)
     }
+
+    /// Get the IPs of the given country codes. `filter` only applies to RIR
+    /// sources.
+    pub fn get_ips(
+        &self,
+        countries: &[impl AsRef<str>],
+        filter: &RecordFilter,
+    ) -> Result<Vec<IpNet>> {
+        get_ips(self, countries, filter)
+    }
+
+    /// Get the registries whose delegated-stats files the source reads.
+    pub fn registries(&self) -> Vec<Registry> {
+        match self {
+            Source::apnic => vec![Registry::Apnic],
+            Source::arin => vec![Registry::Arin],
+            Source::ripencc => vec![Registry::RipeNcc],
+            Source::lacnic => vec![Registry::Lacnic],
+            Source::afrinic => vec![Registry::Afrinic],
+            Source::rir => Registry::ALL.to_vec(),
+            _ => vec![],
+        }
+    }
+
+    /// Get the CN IPs, widened to at most `max_routes` prefixes. See
+    /// [`cidr::summarize`].
+    pub fn get_cn_ips_summarized(&self, max_routes: usize) -> Result<Summary> {
+        Ok(cidr::summarize(&self.get_cn_ips()?, max_routes))
+    }
+}
+
+/// A provider of IP data, which can be passed to [`crate::up`],
+/// [`crate::down`] and [`crate::Target::export_str`] to plug in data that is
+/// not built in.
+///
+/// Only [`name`](Self::name), [`fetch`](Self::fetch) and
+/// [`parse`](Self::parse) have to be implemented. The fetched data is then kept
+/// in a cache file named after the source.
+pub trait IpSource {
+    /// Name of the source, used in logs and as the cache file name.
+    fn name(&self) -> String;
+
+    /// Fetch the raw data.
+    fn fetch(&self) -> Result<Vec<u8>>;
+
+    /// Parse the IPs of the given country codes that pass `filter` from the raw
+    /// data. Sources without countries or records may ignore them.
+    fn parse(&self, data: &[u8], countries: &[String], filter: &RecordFilter)
+        -> Result<Vec<IpNet>>;
+
+    /// How long the fetched data is cached, or [`None`] to fetch it every time.
+    /// Defaults to 7 days.
+    fn cache_expire(&self) -> Option<Duration> {
+        Some(CACHE_EXPIRE)
+    }
+
+    /// Data to use when fetching fails, if there is some.
+    fn builtin(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Get the raw data last saved in cache, even if it has expired, or
+    /// [`None`] if nothing is cached.
+    fn cached(&self) -> Result<Option<Vec<u8>>> {
+        Ok(Cache::new(self.name(), CACHE_EXPIRE).load_stale()?)
+    }
+
+    /// Get the IPs of the given country codes that pass `filter`, from cache or
+    /// freshly fetched.
+    fn get_ips(&self, countries: &[String], filter: &RecordFilter) -> Result<Vec<IpNet>> {
+        fetch_cached_with(
+            &self.name(),
+            self.cache_expire(),
+            || Ok((self.fetch()?, Validators::default())),
+            || self.builtin(),
+            |data| self.parse(data, countries, filter),
+        )
+    }
+}
+
+impl IpSource for Source {
+    fn name(&self) -> String {
+        match self {
+            Self::apnic => "apnic".to_string(),
+            Self::arin => "arin".to_string(),
+            Self::ripencc => "ripencc".to_string(),
+            Self::lacnic => "lacnic".to_string(),
+            Self::afrinic => "afrinic".to_string(),
+            Self::rir => "rir".to_string(),
+            Self::chnroutes2 => "chnroutes2".to_string(),
+            Self::geoip(location) => format!("geoip:{location}"),
+            Self::mmdb(path) => format!("mmdb:{path}"),
+            Self::list(location) => location.clone(),
+            #[cfg(test)]
+            Self::test => "test".to_string(),
+        }
+    }
+
+    fn fetch(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::apnic => Registry::Apnic.fetch(),
+            Self::arin => Registry::Arin.fetch(),
+            Self::ripencc => Registry::RipeNcc.fetch(),
+            Self::lacnic => Registry::Lacnic.fetch(),
+            Self::afrinic => Registry::Afrinic.fetch(),
+            // Delegated-stats files can simply be concatenated.
+            Self::rir => Ok(Registry::ALL
+                .iter()
+                .map(|r| r.fetch())
+                .collect::<Result<Vec<_>>>()?
+                .concat()),
+            Self::chnroutes2 => download(chnroutes2::URL),
+            Self::geoip(location) => {
+                let data = read_location(location)?;
+                if let Some(checksum) = geoip::checksum(location) {
+                    checksum.verify(location, &data)?;
+                }
+                Ok(data)
+            }
+            Self::list(location) => read_location(location),
+            Self::mmdb(path) => Ok(std::fs::read(path)?),
+            #[cfg(test)]
+            Self::test => Ok(b"1.0.1.0/24\n1.0.2.0/23\n".to_vec()),
+        }
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        countries: &[String],
+        filter: &RecordFilter,
+    ) -> Result<Vec<IpNet>> {
+        match self {
+            Self::apnic => Registry::Apnic.parse(data, countries, filter),
+            Self::arin => Registry::Arin.parse(data, countries, filter),
+            Self::ripencc => Registry::RipeNcc.parse(data, countries, filter),
+            Self::lacnic => Registry::Lacnic.parse(data, countries, filter),
+            Self::afrinic => Registry::Afrinic.parse(data, countries, filter),
+            // The concatenated files share the delegated-stats format, which
+            // every registry parses the same way.
+            Self::rir => Registry::Apnic.parse(data, countries, filter),
+            Self::chnroutes2 if !chnroutes2::provides(countries) => Ok(vec![]),
+            Self::chnroutes2 => chnroutes2::parse_ip_data(&String::from_utf8_lossy(data)),
+            Self::geoip(_) => geoip::parse_ip_data(data, countries),
+            Self::mmdb(_) => mmdb::parse_ip_data(&maxminddb::Reader::from_source(data)?, countries),
+            Self::list(_) => list::parse_ip_data(&String::from_utf8_lossy(data)),
+            #[cfg(test)]
+            Self::test => list::parse_ip_data(&String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Remote data is cached, local files are read every time. Registry and
+    /// chnroutes2 data is revalidated with the server after 6 hours.
+    fn cache_expire(&self) -> Option<Duration> {
+        match self {
+            Self::apnic
+            | Self::arin
+            | Self::ripencc
+            | Self::lacnic
+            | Self::afrinic
+            | Self::rir
+            | Self::chnroutes2 => Some(REVALIDATE_EXPIRE),
+            Self::mmdb(_) => None,
+            Self::geoip(location) | Self::list(location)
+                if !location.starts_with("http://") && !location.starts_with("https://") =>
+            {
+                None
+            }
+            _ => Some(CACHE_EXPIRE),
+        }
+    }
+
+    fn builtin(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::apnic => Registry::Apnic.builtin(),
+            _ => None,
+        }
+    }
+
+    /// Look up the cache files the built-in fetchers write, which are not all
+    /// named after the source.
+    fn cached(&self) -> Result<Option<Vec<u8>>> {
+        let load = |name: &str| -> Result<_> { Ok(Cache::new(name, CACHE_EXPIRE).load_stale()?) };
+        match self {
+            Self::rir => Ok(Registry::ALL
+                .iter()
+                .map(|r| load(r.name()))
+                .collect::<Result<Option<Vec<_>>>>()?
+                .map(|data| data.concat())),
+            Self::geoip(location) if location == geoip::DEFAULT_URL => load("geoip"),
+            Self::geoip(location) => load(&url_cache_name("geoip", location)),
+            Self::list(location) => load(&url_cache_name("list", location)),
+            Self::mmdb(_) => Ok(None),
+            _ => load(&self.name()),
+        }
+    }
+
+    fn get_ips(&self, countries: &[String], filter: &RecordFilter) -> Result<Vec<IpNet>> {
+        get_ips(self, countries, filter)
+    }
 }
 
-pub fn get_cn_ips(source: &Source) -> crate::error::Result<Vec<IpNet>> {
+pub fn get_cn_ips(source: &Source) -> Result<Vec<IpNet>> {
+    get_ips(source, &["CN"], &RecordFilter::default())
+}
+
+/// Get the IPs of the given country codes from `source`. `filter` only applies
+/// to RIR sources.
+pub fn get_ips(
+    source: &Source,
+    countries: &[impl AsRef<str>],
+    filter: &RecordFilter,
+) -> Result<Vec<IpNet>> {
     match source {
-        Source::apnic => apnic::fetch_ip_data(),
+        Source::apnic => apnic::fetch_registry_data(Registry::Apnic, countries, filter),
+        Source::arin => apnic::fetch_registry_data(Registry::Arin, countries, filter),
+        Source::ripencc => apnic::fetch_registry_data(Registry::RipeNcc, countries, filter),
+        Source::lacnic => apnic::fetch_registry_data(Registry::Lacnic, countries, filter),
+        Source::afrinic => apnic::fetch_registry_data(Registry::Afrinic, countries, filter),
+        Source::rir => apnic::fetch_all_registries_data(countries, filter),
+        Source::chnroutes2 => chnroutes2::fetch_ip_data(countries),
+        Source::geoip(location) => geoip::fetch_ip_data(location, countries),
+        Source::mmdb(path) => mmdb::fetch_ip_data(path, countries),
+        Source::list(location) => list::fetch_ip_data(location),
         #[cfg(test)]
         Source::test => Ok(vec![
             IpNet::from_str("1.0.1.0/24").unwrap(),
@@ -33,3 +322,496 @@ pub fn get_cn_ips(source: &Source) -> crate::error::Result<Vec<IpNet>> {
         ]),
     }
 }
+
+/// Load the data of `name` from cache, or fetch it from `url` and cache it if
+/// the cache is missing, expired or fails to parse. If fetching fails, the
+/// previous cache or the `builtin` data is used instead when there is some.
+///
+/// Expired data is revalidated with the server, and only downloaded again when
+/// it has changed. Downloaded data has to pass `checks`.
+pub(crate) fn fetch_cached<T>(
+    name: &str,
+    url: &str,
+    checks: Checks,
+    builtin: impl FnOnce() -> Option<Vec<u8>>,
+    parse: impl Fn(&[u8]) -> Result<T>,
+) -> Result<T> {
+    fetch_cached_url(name, url, checks, REVALIDATE_EXPIRE, builtin, parse)
+}
+
+/// Like [`fetch_cached`], with the data kept in cache for `expire`.
+fn fetch_cached_url<T>(
+    name: &str,
+    url: &str,
+    checks: Checks,
+    expire: Duration,
+    builtin: impl FnOnce() -> Option<Vec<u8>>,
+    parse: impl Fn(&[u8]) -> Result<T>,
+) -> Result<T> {
+    let fetch = || {
+        let cache = Cache::new(name, expire);
+        // Validators without the data they belong to are useless.
+        let stale = cache.load_stale()?;
+        let validators = match stale {
+            Some(_) => cache.load_validators()?,
+            None => Validators::default(),
+        };
+        // Built-in data may be far behind, so downloads are not compared with it.
+        let builtin = stale.is_some() && cache.is_builtin()?;
+        match (download_if_modified(url, &validators)?, stale) {
+            (Some(fetched), stale) => {
+                let previous = stale.as_deref().filter(|_| !builtin);
+                checks.verify(url, &fetched.0, previous)?;
+                Ok(fetched)
+            }
+            (None, Some(data)) => {
+                info!("{name} data is not modified, refreshing the cache");
+                Ok((data, validators))
+            }
+            (None, None) => unreachable!("not modified without validators"),
+        }
+    };
+    fetch_cached_with(name, Some(expire), fetch, builtin, parse)
+}
+
+/// Like [`fetch_cached`], but the data and its validators are got by `fetch`
+/// and kept in cache for `expire`, or not cached at all if it is [`None`].
+pub(crate) fn fetch_cached_with<T>(
+    name: &str,
+    expire: Option<Duration>,
+    fetch: impl FnOnce() -> Result<(Vec<u8>, Validators)>,
+    builtin: impl FnOnce() -> Option<Vec<u8>>,
+    parse: impl Fn(&[u8]) -> Result<T>,
+) -> Result<T> {
+    let cache = expire.map(|expire| Cache::new(name, expire));
+    let load = |cache: &Cache| -> Result<Option<T>> {
+        if let Some(data) = cache.load()? {
+            info!("Loading {name} data from cache ...");
+            match parse(&data) {
+                Ok(parsed) => return Ok(Some(parsed)),
+                // A corrupted cache file should not stop us, fetch it again.
+                Err(e) => {
+                    warn!("Cache file is corrupted, fetching again: {e}");
+                    cache.remove_data()?;
+                }
+            }
+        }
+        Ok(None)
+    };
+    // Only one process fetches at a time, the others wait for the lock and then
+    // load what it saved.
+    let _lock = match &cache {
+        Some(cache) => {
+            if let Some(parsed) = load(cache)? {
+                return Ok(parsed);
+            }
+            let lock = cache.lock()?;
+            if let Some(parsed) = load(cache)? {
+                return Ok(parsed);
+            }
+            Some(lock)
+        }
+        None => None,
+    };
+    info!("Fetching data from {name} ...");
+    let err = match fetch() {
+        Ok((data, validators)) => match parse(&data) {
+            Ok(parsed) => {
+                info!("Fetching data from {name} done");
+                if let Some(cache) = &cache {
+                    cache.save_with_validators(&data, &validators)?;
+                }
+                return Ok(parsed);
+            }
+            Err(e) => e,
+        },
+        Err(e) => e,
+    };
+    // If the data fetch failed, use the previous data or the built-in data
+    // instead. The previous data is left expired, to be fetched again next time.
+    if let Some(cache) = &cache {
+        if let Some(parsed) = cache.load_stale()?.and_then(|data| parse(&data).ok()) {
+            warn!("Fetching data from {name} failed, use previous {name} data: {err}");
+            return Ok(parsed);
+        }
+    }
+    let Some(de) = builtin() else {
+        return Err(err);
+    };
+    warn!("Fetching data from {name} failed, use built-in {name} data: {err}");
+    if let Some(cache) = &cache {
+        cache.save_builtin(&de)?;
+    }
+    parse(&de)
+}
+
+/// Download the data at `url`.
+pub(crate) fn download(url: &str) -> Result<Vec<u8>> {
+    Ok(download_if_modified(url, &Validators::default())?
+        .expect("not modified without validators")
+        .0)
+}
+
+/// Download the data at `url` and its validators, unless the server tells it
+/// has not changed since `validators` were got, then return [`None`].
+pub(crate) fn download_if_modified(
+    url: &str,
+    validators: &Validators,
+) -> Result<Option<(Vec<u8>, Validators)>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let mut request = client.get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send()?;
+    if response.status() == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let header = |name: HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    Ok(Some((response.bytes()?.to_vec(), validators)))
+}
+
+/// Checks on downloaded data before it replaces the cached data.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Checks {
+    pub checksum: Option<Checksum>,
+    /// Get the prefixes of the data to check with [`guard::guards`], failing
+    /// if the data is not well-formed.
+    pub guarded_ips: Option<ParseFn>,
+}
+
+/// Parse IPs from raw data.
+pub(crate) type ParseFn = fn(&[u8]) -> Result<Vec<IpNet>>;
+
+impl Checks {
+    /// Check `data` downloaded from `url`, against the `previous` data if
+    /// there is some.
+    pub(crate) fn verify(&self, url: &str, data: &[u8], previous: Option<&[u8]>) -> Result<()> {
+        if let Some(checksum) = &self.checksum {
+            checksum.verify(url, data)?;
+        }
+        if let Some(guarded_ips) = self.guarded_ips {
+            // Previous data that cannot be read is not worth comparing with.
+            let previous = previous.and_then(|data| guarded_ips(data).ok());
+            guard::guards().check(&guarded_ips(data)?, previous.as_deref())?;
+        }
+        Ok(())
+    }
+}
+
+/// Location of the published checksum of downloaded data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Checksum {
+    /// MD5 file, as the RIRs publish next to their files.
+    Md5(String),
+    /// SHA-256 file, as written by `sha256sum`.
+    Sha256(String),
+}
+
+impl Checksum {
+    /// Download the checksum and check `data` downloaded from `url` against it.
+    pub(crate) fn verify(&self, url: &str, data: &[u8]) -> Result<()> {
+        let (location, actual) = match self {
+            Self::Md5(location) => (location, format!("{:x}", Md5::digest(data))),
+            Self::Sha256(location) => (location, format!("{:x}", Sha256::digest(data))),
+        };
+        // Like `MD5 (file) = <hex>` or `<hex>  file`.
+        let published = String::from_utf8_lossy(&download(location)?).to_lowercase();
+        let expected = published
+            .split(|c: char| !c.is_ascii_hexdigit())
+            .find(|x| x.len() == actual.len())
+            .ok_or_else(|| Error::DecodeError(format!("no checksum found in {location}")))?;
+        if expected != actual {
+            return Err(Error::ChecksumMismatch {
+                url: url.to_string(),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Read the data at `location`, an `http(s)://` URL or a local path optionally
+/// written as `file://<path>`.
+pub(crate) fn read_location(location: &str) -> Result<Vec<u8>> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return download(location);
+    }
+    let path = location.strip_prefix("file://").unwrap_or(location);
+    Ok(std::fs::read(path)?)
+}
+
+/// Fetch the data at `location`, an `http(s)://` URL that is cached under a
+/// name derived from `prefix`, or a local path optionally written as
+/// `file://<path>`.
+pub(crate) fn fetch_location<T>(
+    prefix: &str,
+    location: &str,
+    parse: impl Fn(&[u8]) -> Result<T>,
+) -> Result<T> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let name = url_cache_name(prefix, location);
+        return fetch_cached(&name, location, Checks::default(), || None, parse);
+    }
+    parse(&read_location(location)?)
+}
+
+/// Get a cache file name for data fetched from an arbitrary URL. It is derived
+/// from the SHA-256 of the URL, so it stays the same across builds.
+pub(crate) fn url_cache_name(prefix: &str, url: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(url));
+    format!("{prefix}-{}", &digest[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    use super::*;
+    use crate::Options;
+
+    /// A source defined outside of the crate's built-in ones.
+    struct Static;
+
+    impl IpSource for Static {
+        fn name(&self) -> String {
+            "static".to_string()
+        }
+
+        fn fetch(&self) -> Result<Vec<u8>> {
+            Ok(b"CN 1.0.1.0/24\nCN 1.0.0.0/24\nJP 1.0.16.0/20\n".to_vec())
+        }
+
+        fn parse(
+            &self,
+            data: &[u8],
+            countries: &[String],
+            _filter: &RecordFilter,
+        ) -> Result<Vec<IpNet>> {
+            Ok(String::from_utf8_lossy(data)
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .filter(|(cc, _)| countries.iter().any(|c| c == cc))
+                .map(|(_, net)| net.parse().unwrap())
+                .collect())
+        }
+
+        fn cache_expire(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    /// Serve `responses` over HTTP one by one, like a local mirror. Returns
+    /// the base URL and the lowercased head of every request.
+    fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    request.push_str(&line.to_lowercase());
+                    request.push('\n');
+                }
+                write!(stream, "HTTP/1.1 {response}").unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    /// An HTTP response with `body`.
+    fn ok(body: &str) -> String {
+        format!("200 OK\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    fn parse_list(data: &[u8]) -> Result<Vec<IpNet>> {
+        list::parse_ip_data(&String::from_utf8_lossy(data))
+    }
+
+    #[test]
+    fn test_conditional_refresh() {
+        crate::cache::use_test_cache_dir();
+        let (url, server) = serve(vec![
+            "200 OK\r\nETag: \"v1\"\r\nContent-Length: 11\r\n\r\n1.0.1.0/24\n".to_string(),
+            "304 Not Modified\r\nETag: \"v1\"\r\n\r\n".to_string(),
+        ]);
+        let url = format!("{url}/list.txt");
+        let name = "test-conditional-refresh";
+        let cache = Cache::new(name, Duration::ZERO);
+        cache.remove().unwrap();
+        // Expired right away, so the second fetch revalidates the first one.
+        for _ in 0..2 {
+            let ips = fetch_cached_url(
+                name,
+                &url,
+                Checks::default(),
+                Duration::ZERO,
+                || None,
+                parse_list,
+            );
+            assert_eq!(ips.unwrap(), [IpNet::from_str("1.0.1.0/24").unwrap()]);
+        }
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert_eq!(cache.load_validators().unwrap().etag.unwrap(), "\"v1\"");
+        cache.remove().unwrap();
+    }
+
+    #[test]
+    fn test_checksum() {
+        crate::cache::use_test_cache_dir();
+        let data = "1.0.1.0/24\n";
+        let md5 = "MD5 (list.txt) = 1943e0e62a5c51c9c3720d1c3a2fa26f";
+        let (url, server) = serve(vec![
+            ok(data),
+            ok(&md5.replace("1943", "0000")),
+            ok(data),
+            ok(&md5.replace("1943", "0000")),
+            ok(data),
+            ok(md5),
+        ]);
+        let url = format!("{url}/list.txt");
+        let checks = || Checks {
+            checksum: Some(Checksum::Md5(format!("{url}.md5"))),
+            ..Default::default()
+        };
+        let name = "test-checksum";
+        let cache = Cache::new(name, Duration::ZERO);
+        cache.remove().unwrap();
+        let fetch = || fetch_cached_url(name, &url, checks(), Duration::ZERO, || None, parse_list);
+
+        assert!(matches!(
+            fetch().unwrap_err(),
+            Error::ChecksumMismatch { expected, .. } if expected.starts_with("0000")
+        ));
+        assert_eq!(cache.load_stale().unwrap(), None);
+        // The previous data is kept.
+        cache.save(b"1.0.2.0/24\n").unwrap();
+        assert_eq!(fetch().unwrap(), [IpNet::from_str("1.0.2.0/24").unwrap()]);
+        assert_eq!(cache.load_stale().unwrap().unwrap(), b"1.0.2.0/24\n");
+        assert_eq!(fetch().unwrap(), [IpNet::from_str("1.0.1.0/24").unwrap()]);
+        assert_eq!(cache.load_stale().unwrap().unwrap(), data.as_bytes());
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("get /list.txt.md5 "));
+        cache.remove().unwrap();
+    }
+
+    #[test]
+    fn test_guards() {
+        crate::cache::use_test_cache_dir();
+        let (url, server) = serve(vec![
+            ok("<html><body>Please log in</body></html>"),
+            ok("1.0.1.0/24\n"),
+        ]);
+        let name = "test-guards";
+        let cache = Cache::new(name, Duration::ZERO);
+        cache.save(b"1.0.2.0/24\n").unwrap();
+        let checks = || Checks {
+            guarded_ips: Some(parse_list),
+            ..Default::default()
+        };
+        // A login page, then too few prefixes for the default guards.
+        for _ in 0..2 {
+            let ips = fetch_cached_url(name, &url, checks(), Duration::ZERO, || None, parse_list);
+            assert_eq!(ips.unwrap(), [IpNet::from_str("1.0.2.0/24").unwrap()]);
+        }
+        assert_eq!(cache.load_stale().unwrap().unwrap(), b"1.0.2.0/24\n");
+        server.join().unwrap();
+        cache.remove().unwrap();
+    }
+
+    #[test]
+    fn test_builtin_baseline() {
+        crate::cache::use_test_cache_dir();
+        // 20% more addresses than the built-in data, over the default guards.
+        let nets = |count: usize| -> String {
+            (0..count)
+                .map(|i| format!("1.{}.{}.0/24\n", i / 256, i % 256))
+                .collect()
+        };
+        let (old, new) = (nets(1000), nets(1200));
+        let (url, server) = serve(vec![
+            ok("<html><body>Please log in</body></html>"),
+            ok(&new),
+            ok(&new),
+        ]);
+        let name = "test-builtin-baseline";
+        let cache = Cache::new(name, Duration::ZERO);
+        cache.remove().unwrap();
+        let checks = || Checks {
+            guarded_ips: Some(parse_list),
+            ..Default::default()
+        };
+        let fetch = |builtin: Option<&str>| {
+            let builtin = || builtin.map(|data| data.as_bytes().to_vec());
+            fetch_cached_url(name, &url, checks(), Duration::ZERO, builtin, parse_list)
+                .unwrap()
+                .len()
+        };
+
+        assert_eq!(fetch(Some(&old)), 1000);
+        assert!(cache.is_builtin().unwrap());
+        // The built-in data is replaced, however much the download differs.
+        assert_eq!(fetch(None), 1200);
+        assert!(!cache.is_builtin().unwrap());
+        // Previous downloads are still compared with.
+        cache.save(old.as_bytes()).unwrap();
+        assert_eq!(fetch(None), 1000);
+        assert_eq!(cache.load_stale().unwrap().unwrap(), old.as_bytes());
+        server.join().unwrap();
+        cache.remove().unwrap();
+    }
+
+    #[test]
+    fn test_ip_source() {
+        assert_eq!(
+            Options::default().get_ips(&Static).unwrap(),
+            [IpNet::from_str("1.0.0.0/23").unwrap()]
+        );
+        let source: Box<dyn IpSource> = Box::new(Source::test);
+        assert_eq!(source.name(), "test");
+        assert_eq!(
+            Options::default().get_ips(source.as_ref()).unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_url_cache_name() {
+        assert_eq!(
+            url_cache_name("list", "https://example.com/list.txt"),
+            "list-2903a3aa42833ffa"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Statistics of the IP list of a source.
+
+use std::collections::BTreeMap;
+
+use ipnet::IpNet;
+
+use crate::{
+    cidr,
+    source::apnic::{self, Header, RecordType},
+    Composite, IpSource, Options, Result,
+};
+
+/// Statistics of the prefixes of one family.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FamilyStats {
+    pub prefixes: usize,
+    /// Distinct addresses covered by the prefixes.
+    pub addresses: u128,
+    /// Number of prefixes of each length.
+    pub lengths: BTreeMap<u8, usize>,
+}
+
+impl FamilyStats {
+    /// Count the prefixes of one family in `ips`.
+    pub fn new(ips: &[IpNet], ipv6: bool) -> Self {
+        let mut stats = Self {
+            addresses: cidr::address_count(ips, ipv6),
+            ..Default::default()
+        };
+        for net in ips.iter().filter(|net| net.addr().is_ipv6() == ipv6) {
+            stats.prefixes += 1;
+            *stats.lengths.entry(net.prefix_len()).or_default() += 1;
+        }
+        stats
+    }
+}
+
+/// Result of [`stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// IP records of the selected countries that pass the record filter, for
+    /// sources reading RIR files. Otherwise the prefixes the source lists.
+    pub records: usize,
+    /// Prefixes of the source after the options are applied, without local
+    /// networks, `include` and `complement`, see [`Options::source_options`].
+    pub v4: FamilyStats,
+    pub v6: FamilyStats,
+    /// Headers of the RIR files the source reads.
+    pub headers: Vec<Header>,
+}
+
+impl Stats {
+    /// Number of prefixes of both families.
+    pub fn prefixes(&self) -> usize {
+        self.v4.prefixes + self.v6.prefixes
+    }
+
+    /// IPv6 addresses in units of `/32` blocks.
+    pub fn ipv6_slash32s(&self) -> f64 {
+        self.v6.addresses as f64 / 2f64.powi(96)
+    }
+}
+
+/// Get the statistics of the IP list of `source` with `options` applied.
+pub fn stats(source: &Composite, options: &Options) -> Result<Stats> {
+    let ips = options.source_options().get_ips(source)?;
+    let registries = source.registries();
+    let mut stats = Stats {
+        v4: FamilyStats::new(&ips, false),
+        v6: FamilyStats::new(&ips, true),
+        ..Default::default()
+    };
+    if registries.is_empty() {
+        stats.records = source
+            .get_ips(&options.countries, &options.record_filter)?
+            .len();
+    }
+    for registry in registries {
+        stats.records += apnic::select_records(
+            &apnic::fetch_registry_records(registry)?,
+            &options.countries,
+            &options.record_filter,
+        )
+        .filter(|r| r.kind != RecordType::Asn)
+        .count();
+        stats
+            .headers
+            .extend(apnic::fetch_registry_headers(registry)?);
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::Source;
+
+    #[test]
+    fn test_stats() {
+        let stats = stats(&Source::test.into(), &Options::default()).unwrap();
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.prefixes(), 2);
+        assert_eq!(stats.v4.addresses, 768);
+        assert_eq!(stats.v4.lengths, BTreeMap::from([(23, 1), (24, 1)]));
+        assert!(stats.headers.is_empty());
+        // local networks, included prefixes and the complement are not part of
+        // the source
+        let options = Options {
+            complement: true,
+            include: vec![IpNet::from_str("1.0.4.0/24").unwrap()],
+            local_networks: Some(cidr::local_networks()),
+            ..Default::default()
+        };
+        assert_eq!(super::stats(&Source::test.into(), &options).unwrap(), stats);
+
+        let ips =
+            ["2001:250::/31", "2001:252::/32", "1.0.0.0/24"].map(|x| IpNet::from_str(x).unwrap());
+        let v6 = FamilyStats::new(&ips, true);
+        assert_eq!(v6.prefixes, 2);
+        assert_eq!(v6.lengths, BTreeMap::from([(31, 1), (32, 1)]));
+        let stats = Stats {
+            v6,
+            ..Default::default()
+        };
+        assert_eq!(stats.ipv6_slash32s(), 3.0);
+    }
+}
@@ -2,7 +2,7 @@ use std::{path::PathBuf, str::FromStr};
 
 use ipnet::IpNet;
 
-use crate::{source::Source, Error, Result};
+use crate::{cidr, options::Options, source::IpSource, Error, Result};
 
 /// The Targets for exporting scripts, like chnroutes.py do.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,6 +12,10 @@ pub enum Target {
     Mac,
     Windows,
     Android,
+    /// `AllowedIPs` line of a WireGuard peer, routed through the tunnel.
+    WireGuard,
+    /// Plain CIDR list, one prefix per line.
+    Cidr,
 }
 
 impl Target {
@@ -20,23 +24,66 @@ impl Target {
     /// # Returns
     ///
     /// OpenVPN => Append the content to openvpn config file.
+    /// WireGuard => Put the line in the `[Peer]` section of the config file.
+    /// Cidr => The prefixes, one per line.
     /// Others => Return two Strings representing the upscript and downscript.
-    pub fn export_str(&self, source: &Source) -> crate::error::Result<(String, Option<String>)> {
-        let source_ips = source.get_cn_ips()?;
+    ///
+    /// With [`Options::complement`], the prefixes go through the tunnel instead
+    /// of the original gateway. The scripts of other targets only route through
+    /// the original gateway, so they return [`Error::Unsupported`].
+    pub fn export_str(
+        &self,
+        source: &(impl IpSource + ?Sized),
+        options: &Options,
+    ) -> crate::error::Result<(String, Option<String>)> {
+        if options.complement && !self.supports_complement() {
+            return Err(Error::Unsupported(
+                "complement is only supported by the openvpn, wireguard and cidr targets",
+            ));
+        }
+        let mut options = options.clone();
+        options
+            .local_networks
+            .get_or_insert_with(|| self.default_local_networks());
+        let source_ips = options.get_ips(source)?;
         match self {
-            Self::OpenVPN(metric) => Ok((export_openvpn(source_ips, metric), None)),
+            Self::OpenVPN(metric) => {
+                Ok((export_openvpn(source_ips, metric, options.complement), None))
+            }
             Self::Linux => Ok(export_linux(source_ips)),
             Self::Mac => Ok(export_mac(source_ips)),
             Self::Windows => Ok(export_windows(source_ips)),
             Self::Android => Ok(export_android(source_ips)),
+            Self::WireGuard => Ok((export_wireguard(source_ips), None)),
+            Self::Cidr => Ok((export_cidr(source_ips), None)),
+        }
+    }
+
+    /// Local networks used when [`Options::local_networks`] is [`None`]: the
+    /// private ranges for the scripts, like they always routed directly.
+    fn default_local_networks(&self) -> Vec<IpNet> {
+        match self {
+            Self::Linux | Self::Mac | Self::Windows | Self::Android => cidr::private_networks(),
+            Self::OpenVPN(_) | Self::WireGuard | Self::Cidr => vec![],
         }
     }
 
+    /// Whether the target can route the prefixes through the tunnel.
+    fn supports_complement(&self) -> bool {
+        matches!(self, Self::OpenVPN(_) | Self::WireGuard | Self::Cidr)
+    }
+
     /// write the export script as file to current dir.
-    pub fn export_file(&self, source: &Source) -> crate::error::Result<()> {
-        let (up, down) = self.export_str(source)?;
+    pub fn export_file(
+        &self,
+        source: &(impl IpSource + ?Sized),
+        options: &Options,
+    ) -> crate::error::Result<()> {
+        let (up, down) = self.export_str(source, options)?;
         match self {
             Self::OpenVPN(_) => std::fs::write("openvpn_conf.txt", up)?,
+            Self::WireGuard => std::fs::write("wireguard_conf.txt", up)?,
+            Self::Cidr => std::fs::write("cidr.txt", up)?,
             other => {
                 let mut up_filename = PathBuf::from("up");
                 let mut down_filename = PathBuf::from("down");
@@ -66,18 +113,28 @@ impl FromStr for Target {
             "mac" => Ok(Self::Mac),
             "windows" => Ok(Self::Windows),
             "android" => Ok(Self::Android),
+            "wireguard" => Ok(Self::WireGuard),
+            "cidr" => Ok(Self::Cidr),
             _ => Err(Error::InvalidTarget),
         }
     }
 }
 
-fn export_openvpn(ips: Vec<IpNet>, metric: &u32) -> String {
+/// Route `ips` through the original gateway, or through the tunnel with
+/// `complement`.
+fn export_openvpn(ips: Vec<IpNet>, metric: &u32, complement: bool) -> String {
+    let gateway = if complement {
+        "vpn_gateway"
+    } else {
+        "net_gateway"
+    };
     ips.into_iter()
         .map(|ip| {
             format!(
-                "route {} {} net_gateway {}",
+                "route {} {} {} {}",
                 ip.addr(),
                 ip.netmask(),
+                gateway,
                 metric
             )
         })
@@ -85,6 +142,23 @@ fn export_openvpn(ips: Vec<IpNet>, metric: &u32) -> String {
         .join("\n")
 }
 
+fn export_wireguard(ips: Vec<IpNet>) -> String {
+    format!(
+        "AllowedIPs = {}",
+        ips.iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+fn export_cidr(ips: Vec<IpNet>) -> String {
+    ips.iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 fn export_linux(ips: Vec<IpNet>) -> (String, Option<String>) {
     let mut up = r#"#!/bin/bash
 export PATH="/bin:/sbin:/usr/sbin:/usr/bin"
@@ -143,10 +217,6 @@ fi
 
 dscacheutil -flushcache
 
-route add 10.0.0.0/8 "${OLDGW}"
-route add 172.16.0.0/12 "${OLDGW}"
-route add 192.168.0.0/16 "${OLDGW}"
-
 "#.to_string();
 
     let mut down = r#"#!/bin/sh
@@ -158,10 +228,6 @@ fi
 
 OLDGW=`cat /tmp/pptp_oldgw`
 
-route delete 10.0.0.0/8 "${OLDGW}"
-route delete 172.16.0.0/12 "${OLDGW}"
-route delete 192.168.0.0/16 "${OLDGW}"
-
 "#
     .to_string();
 
@@ -264,12 +330,83 @@ mod tests {
     use std::path::Path;
 
     use super::*;
+    use crate::Source;
 
     #[test]
     fn test_export_file() {
-        Target::Windows.export_file(&Source::test).unwrap();
+        Target::Windows
+            .export_file(&Source::test, &Default::default())
+            .unwrap();
         let up = Path::new("up.bat");
         let down = Path::new("down.bat");
         assert!(up.exists() && down.exists());
     }
+
+    #[test]
+    fn test_export_local_networks() {
+        // private networks stay direct by default, like the old mac scripts
+        let (up, down) = Target::Mac
+            .export_str(&Source::test, &Default::default())
+            .unwrap();
+        for net in ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"] {
+            assert!(up.contains(&format!(r#"route add {net} "${{OLDGW}}""#)));
+            assert!(down
+                .as_ref()
+                .unwrap()
+                .contains(&format!("route delete {net} ")));
+        }
+        let (up, _) = Target::Linux
+            .export_str(&Source::test, &Default::default())
+            .unwrap();
+        assert!(up.contains("route add -net 192.168.0.0 netmask 255.255.0.0 gw $OLDGW"));
+        assert!(!up.contains("169.254.0.0") && !up.contains("224.0.0.0"));
+        let options = Options {
+            local_networks: Some(vec![]),
+            ..Default::default()
+        };
+        let (up, _) = Target::Mac.export_str(&Source::test, &options).unwrap();
+        assert!(!up.contains("192.168.0.0"));
+        let (up, _) = Target::Cidr
+            .export_str(&Source::test, &Default::default())
+            .unwrap();
+        assert_eq!(up, "1.0.1.0/24\n1.0.2.0/23");
+    }
+
+    #[test]
+    fn test_export_complement() {
+        let options = Options {
+            complement: true,
+            ..Default::default()
+        };
+        let (up, down) = Target::OpenVPN(1)
+            .export_str(&Source::test, &options)
+            .unwrap();
+        assert!(down.is_none());
+        assert!(up.lines().all(|line| line.ends_with(" vpn_gateway 1")));
+        assert!(up.contains("route 8.0.0.0 254.0.0.0 vpn_gateway 1"));
+        assert!(!up.contains("route 1.0.2.0 "));
+        let (up, _) = Target::OpenVPN(1)
+            .export_str(&Source::test, &Default::default())
+            .unwrap();
+        assert_eq!(
+            up,
+            "route 1.0.1.0 255.255.255.0 net_gateway 1\nroute 1.0.2.0 255.255.254.0 net_gateway 1"
+        );
+
+        let (up, _) = Target::WireGuard
+            .export_str(&Source::test, &options)
+            .unwrap();
+        assert!(up.starts_with("AllowedIPs = 1.0.0.0/24, "));
+        assert!(up.contains(", 8.0.0.0/7, "));
+        assert!(!up.contains("1.0.2.0/"));
+        let (up, _) = Target::Cidr.export_str(&Source::test, &options).unwrap();
+        assert!(up.lines().any(|line| line == "8.0.0.0/7"));
+
+        for target in [Target::Linux, Target::Mac, Target::Windows, Target::Android] {
+            assert!(matches!(
+                target.export_str(&Source::test, &options),
+                Err(Error::Unsupported(_))
+            ));
+        }
+    }
 }
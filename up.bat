@@ -0,0 +1,16 @@
+@echo off
+for /F "tokens=3" %%* in ('route print ^| findstr "\<0.0.0.0\>"') do set "gw=%%*"
+echo gw=%gw%
+ipconfig /flushdns
+
+route add 1.0.1.0 mask 255.255.255.0 %gw% metric 1
+route add 1.0.2.0 mask 255.255.254.0 %gw% metric 1
+route add 10.0.0.0 mask 255.0.0.0 %gw% metric 1
+route add 100.64.0.0 mask 255.192.0.0 %gw% metric 1
+route add 169.254.0.0 mask 255.255.0.0 %gw% metric 1
+route add 172.16.0.0 mask 255.240.0.0 %gw% metric 1
+route add 192.168.0.0 mask 255.255.0.0 %gw% metric 1
+route add 224.0.0.0 mask 240.0.0.0 %gw% metric 1
+route add fc00:: mask fe00:: %gw% metric 1
+route add fe80:: mask ffc0:: %gw% metric 1
+route add ff00:: mask ff00:: %gw% metric 1
\ No newline at end of file